@@ -0,0 +1,69 @@
+use bevy::math::Vec2;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use combobox::physics::SpatialGrid;
+
+const RADIUS: f32 = 5.0;
+
+/// Random ball centers in a square box sized to keep the density constant,
+/// roughly as packed as a box full of the smallest items.
+fn positions(count: usize) -> Vec<Vec2> {
+    let side = (count as f32).sqrt() * RADIUS * 2.5;
+    let mut rng = StdRng::seed_from_u64(42);
+    (0..count)
+        .map(|_| Vec2::new(rng.gen_range(0.0..side), rng.gen_range(0.0..side)))
+        .collect()
+}
+
+fn brute_force(positions: &[Vec2]) -> usize {
+    let mut overlaps = 0;
+    for (i, a) in positions.iter().enumerate() {
+        for b in positions[i + 1..].iter() {
+            if a.distance_squared(*b) < (RADIUS * 2.0).powi(2) {
+                overlaps += 1;
+            }
+        }
+    }
+    overlaps
+}
+
+fn grid(grid: &mut SpatialGrid, pairs: &mut Vec<(usize, usize)>, positions: &[Vec2]) -> usize {
+    grid.clear(RADIUS * 2.0);
+    for (i, position) in positions.iter().enumerate() {
+        grid.insert(i, *position);
+    }
+    pairs.clear();
+    grid.pairs(positions, pairs);
+    pairs
+        .iter()
+        .filter(|(a, b)| positions[*a].distance_squared(positions[*b]) < (RADIUS * 2.0).powi(2))
+        .count()
+}
+
+fn broad_phase(c: &mut Criterion) {
+    let mut group = c.benchmark_group("broad_phase");
+    for count in [100, 1000, 5000] {
+        let positions = positions(count);
+
+        let mut spatial_grid = SpatialGrid::new(RADIUS * 2.0);
+        let mut pairs = vec![];
+        assert_eq!(
+            brute_force(&positions),
+            grid(&mut spatial_grid, &mut pairs, &positions)
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("brute_force", count),
+            &positions,
+            |b, p| b.iter(|| brute_force(p)),
+        );
+        group.bench_with_input(BenchmarkId::new("grid", count), &positions, |b, p| {
+            b.iter(|| grid(&mut spatial_grid, &mut pairs, p))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, broad_phase);
+criterion_main!(benches);
@@ -0,0 +1,606 @@
+use bevy::{ecs::system::SystemParam, input::InputSystem, prelude::*, window::PrimaryWindow};
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    platform::{Platform, PlatformInput, PlatformSystems},
+    GameState,
+};
+
+/// Axis value a gamepad stick has to pass to be bound while remapping.
+const CAPTURE_THRESHOLD: f32 = 0.5;
+
+/// Turns the bound keys, mouse and gamepad inputs into `PlatformInput`, and
+/// lets the player remap them. Insert `Bindings` before the app starts to
+/// override the defaults, and a `BindingsFile` to save the remapped ones.
+pub struct ControlsPlugin;
+
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Bindings>();
+        app.init_resource::<CursorAim>();
+        app.init_resource::<Remapping>();
+        app.init_resource::<DropPressed>();
+        app.add_systems(PreUpdate, latch_drop.after(InputSystem));
+        app.add_systems(FixedUpdate, read_controls.in_set(PlatformSystems::Input));
+        // Escape closes the remapping screen before it can pause.
+        app.add_systems(
+            Update,
+            (
+                update_cursor_aim,
+                (toggle_pause, remap_controls).chain(),
+                save_bindings,
+            ),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    Drop,
+    /// Analog movement, following a stick or the mouse cursor.
+    Aim,
+    Pause,
+}
+
+impl Action {
+    pub const ALL: [Action; 5] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Drop,
+        Action::Aim,
+        Action::Pause,
+    ];
+
+    pub fn is_analog(self) -> bool {
+        self == Action::Aim
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "Move left",
+            Action::MoveRight => "Move right",
+            Action::Drop => "Drop",
+            Action::Aim => "Aim",
+            Action::Pause => "Pause",
+        }
+    }
+}
+
+/// Input an action is bound to. Buttons drive the digital actions and axes
+/// the analog ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButtonType),
+    Axis(GamepadAxisType),
+    /// Position of the mouse cursor over the arena.
+    Cursor,
+}
+
+/// Name of `key` shown to the player, from the US layout as key codes are
+/// positions on the keyboard.
+fn key_name(key: KeyCode) -> Option<&'static str> {
+    Some(match key {
+        KeyCode::KeyA => "A",
+        KeyCode::KeyB => "B",
+        KeyCode::KeyC => "C",
+        KeyCode::KeyD => "D",
+        KeyCode::KeyE => "E",
+        KeyCode::KeyF => "F",
+        KeyCode::KeyG => "G",
+        KeyCode::KeyH => "H",
+        KeyCode::KeyI => "I",
+        KeyCode::KeyJ => "J",
+        KeyCode::KeyK => "K",
+        KeyCode::KeyL => "L",
+        KeyCode::KeyM => "M",
+        KeyCode::KeyN => "N",
+        KeyCode::KeyO => "O",
+        KeyCode::KeyP => "P",
+        KeyCode::KeyQ => "Q",
+        KeyCode::KeyR => "R",
+        KeyCode::KeyS => "S",
+        KeyCode::KeyT => "T",
+        KeyCode::KeyU => "U",
+        KeyCode::KeyV => "V",
+        KeyCode::KeyW => "W",
+        KeyCode::KeyX => "X",
+        KeyCode::KeyY => "Y",
+        KeyCode::KeyZ => "Z",
+        KeyCode::Digit0 => "0",
+        KeyCode::Digit1 => "1",
+        KeyCode::Digit2 => "2",
+        KeyCode::Digit3 => "3",
+        KeyCode::Digit4 => "4",
+        KeyCode::Digit5 => "5",
+        KeyCode::Digit6 => "6",
+        KeyCode::Digit7 => "7",
+        KeyCode::Digit8 => "8",
+        KeyCode::Digit9 => "9",
+        KeyCode::Numpad0 => "Num 0",
+        KeyCode::Numpad1 => "Num 1",
+        KeyCode::Numpad2 => "Num 2",
+        KeyCode::Numpad3 => "Num 3",
+        KeyCode::Numpad4 => "Num 4",
+        KeyCode::Numpad5 => "Num 5",
+        KeyCode::Numpad6 => "Num 6",
+        KeyCode::Numpad7 => "Num 7",
+        KeyCode::Numpad8 => "Num 8",
+        KeyCode::Numpad9 => "Num 9",
+        KeyCode::Space => "Space",
+        KeyCode::Enter | KeyCode::NumpadEnter => "Enter",
+        KeyCode::Escape => "Escape",
+        KeyCode::Tab => "Tab",
+        KeyCode::Backspace => "Backspace",
+        KeyCode::ArrowLeft => "Left",
+        KeyCode::ArrowRight => "Right",
+        KeyCode::ArrowUp => "Up",
+        KeyCode::ArrowDown => "Down",
+        KeyCode::ShiftLeft => "Left Shift",
+        KeyCode::ShiftRight => "Right Shift",
+        KeyCode::ControlLeft => "Left Ctrl",
+        KeyCode::ControlRight => "Right Ctrl",
+        KeyCode::AltLeft => "Left Alt",
+        KeyCode::AltRight => "Right Alt",
+        KeyCode::Home => "Home",
+        KeyCode::End => "End",
+        KeyCode::PageUp => "Page Up",
+        KeyCode::PageDown => "Page Down",
+        KeyCode::Insert => "Insert",
+        KeyCode::Delete => "Delete",
+        KeyCode::Minus => "-",
+        KeyCode::Equal => "=",
+        KeyCode::Comma => ",",
+        KeyCode::Period => ".",
+        KeyCode::Slash => "/",
+        KeyCode::Backslash => "\\",
+        KeyCode::Semicolon => ";",
+        KeyCode::Quote => "'",
+        KeyCode::BracketLeft => "[",
+        KeyCode::BracketRight => "]",
+        KeyCode::Backquote => "`",
+        KeyCode::F1 => "F1",
+        KeyCode::F2 => "F2",
+        KeyCode::F3 => "F3",
+        KeyCode::F4 => "F4",
+        KeyCode::F5 => "F5",
+        KeyCode::F6 => "F6",
+        KeyCode::F7 => "F7",
+        KeyCode::F8 => "F8",
+        KeyCode::F9 => "F9",
+        KeyCode::F10 => "F10",
+        KeyCode::F11 => "F11",
+        KeyCode::F12 => "F12",
+        _ => return None,
+    })
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Binding::Key(key) => match key_name(*key) {
+                Some(name) => f.write_str(name),
+                // Rarely bound keys.
+                None => write!(f, "{key:?}"),
+            },
+            Binding::Mouse(button) => write!(f, "Mouse {button:?}"),
+            Binding::Gamepad(button) => write!(f, "Pad {button:?}"),
+            Binding::Axis(axis) => write!(f, "Pad {axis:?}"),
+            Binding::Cursor => f.write_str("Mouse cursor"),
+        }
+    }
+}
+
+/// Inputs bound to each action, stored as RON in `BindingsFile`.
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bindings {
+    pub move_left: Vec<Binding>,
+    pub move_right: Vec<Binding>,
+    pub drop: Vec<Binding>,
+    pub aim: Vec<Binding>,
+    pub pause: Vec<Binding>,
+    /// Axis values below which sticks are considered centered.
+    pub dead_zone: f32,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self {
+            move_left: vec![
+                Binding::Key(KeyCode::KeyA),
+                Binding::Key(KeyCode::ArrowLeft),
+                Binding::Gamepad(GamepadButtonType::DPadLeft),
+            ],
+            move_right: vec![
+                Binding::Key(KeyCode::KeyD),
+                Binding::Key(KeyCode::ArrowRight),
+                Binding::Gamepad(GamepadButtonType::DPadRight),
+            ],
+            drop: vec![
+                Binding::Key(KeyCode::Space),
+                Binding::Mouse(MouseButton::Left),
+                Binding::Gamepad(GamepadButtonType::South),
+            ],
+            aim: vec![Binding::Axis(GamepadAxisType::LeftStickX), Binding::Cursor],
+            pause: vec![
+                Binding::Key(KeyCode::Escape),
+                Binding::Gamepad(GamepadButtonType::Start),
+            ],
+            dead_zone: 0.15,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum BindingsError {
+    #[error("could not access bindings: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse bindings: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("could not write bindings: {0}")]
+    Serialize(#[from] ron::Error),
+}
+
+impl Bindings {
+    pub fn from_ron(ron: &str) -> Result<Self, BindingsError> {
+        Ok(ron::de::from_str(ron)?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BindingsError> {
+        Self::from_ron(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn to_ron(&self) -> Result<String, BindingsError> {
+        Ok(ron::ser::to_string_pretty(self, PrettyConfig::default())?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BindingsError> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_ron()?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, action: Action) -> &Vec<Binding> {
+        match action {
+            Action::MoveLeft => &self.move_left,
+            Action::MoveRight => &self.move_right,
+            Action::Drop => &self.drop,
+            Action::Aim => &self.aim,
+            Action::Pause => &self.pause,
+        }
+    }
+
+    pub fn get_mut(&mut self, action: Action) -> &mut Vec<Binding> {
+        match action {
+            Action::MoveLeft => &mut self.move_left,
+            Action::MoveRight => &mut self.move_right,
+            Action::Drop => &mut self.drop,
+            Action::Aim => &mut self.aim,
+            Action::Pause => &mut self.pause,
+        }
+    }
+}
+
+/// File the bindings are saved to whenever they are remapped.
+#[derive(Resource, Debug, Clone)]
+pub struct BindingsFile(pub PathBuf);
+
+impl BindingsFile {
+    /// `bindings.ron` in the user configuration directory.
+    pub fn user() -> Option<Self> {
+        crate::config_dir().map(|dir| Self(dir.join("bindings.ron")))
+    }
+
+    /// Bindings saved in the file, the default ones if there are none yet.
+    pub fn load(&self) -> Result<Bindings, BindingsError> {
+        match Bindings::load(&self.0) {
+            Err(BindingsError::Io(error)) if error.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Bindings::default())
+            }
+            result => result,
+        }
+    }
+}
+
+/// Point of the arena under the mouse cursor, followed by the platform since
+/// the cursor last moved.
+#[derive(Resource, Debug, Default)]
+pub struct CursorAim {
+    /// X coordinate the platform moves towards, `None` once other movement
+    /// input took over.
+    pub target: Option<f32>,
+    cursor: Option<Vec2>,
+}
+
+/// Whether a drop binding was pressed since the last physics step, so
+/// presses shorter than a step still drop.
+#[derive(Resource, Debug, Default)]
+struct DropPressed(bool);
+
+/// State of the remapping screen.
+#[derive(Resource, Debug)]
+pub struct Remapping {
+    pub open: bool,
+    pub selected: Action,
+    /// Whether the next input pressed is bound to the selected action.
+    pub listening: bool,
+}
+
+impl Default for Remapping {
+    fn default() -> Self {
+        Self {
+            open: false,
+            selected: Action::MoveLeft,
+            listening: false,
+        }
+    }
+}
+
+/// Inputs read by the controls.
+#[derive(SystemParam)]
+pub struct InputDevices<'w> {
+    pub keys: Res<'w, ButtonInput<KeyCode>>,
+    pub mouse_buttons: Res<'w, ButtonInput<MouseButton>>,
+    pub gamepads: Res<'w, Gamepads>,
+    pub gamepad_buttons: Res<'w, ButtonInput<GamepadButton>>,
+    pub gamepad_axes: Res<'w, Axis<GamepadAxis>>,
+}
+
+impl InputDevices<'_> {
+    pub fn just_pressed(&self, binding: Binding) -> bool {
+        match binding {
+            Binding::Key(key) => self.keys.just_pressed(key),
+            Binding::Mouse(button) => self.mouse_buttons.just_pressed(button),
+            Binding::Gamepad(button_type) => self.gamepads.iter().any(|gamepad| {
+                self.gamepad_buttons
+                    .just_pressed(GamepadButton::new(gamepad, button_type))
+            }),
+            Binding::Axis(_) | Binding::Cursor => false,
+        }
+    }
+
+    pub fn pressed(&self, binding: Binding) -> bool {
+        match binding {
+            Binding::Key(key) => self.keys.pressed(key),
+            Binding::Mouse(button) => self.mouse_buttons.pressed(button),
+            Binding::Gamepad(button_type) => self.gamepads.iter().any(|gamepad| {
+                self.gamepad_buttons
+                    .pressed(GamepadButton::new(gamepad, button_type))
+            }),
+            Binding::Axis(_) | Binding::Cursor => false,
+        }
+    }
+
+    /// Value of the axis furthest from the center, 0 within the dead zone.
+    pub fn axis(&self, binding: Binding, dead_zone: f32) -> f32 {
+        let Binding::Axis(axis_type) = binding else {
+            return 0.0;
+        };
+        self.gamepads
+            .iter()
+            .filter_map(|gamepad| self.gamepad_axes.get(GamepadAxis::new(gamepad, axis_type)))
+            .filter(|value| value.abs() > dead_zone)
+            .max_by(|a, b| a.abs().total_cmp(&b.abs()))
+            .unwrap_or(0.0)
+    }
+
+    /// First input pressed this frame that can be bound to `action`.
+    fn capture(&self, action: Action) -> Option<Binding> {
+        if action.is_analog() {
+            let axis = self.gamepads.iter().find_map(|gamepad| {
+                self.gamepad_axes
+                    .devices()
+                    .filter(|axis| axis.gamepad == gamepad)
+                    .find(|axis| {
+                        self.gamepad_axes
+                            .get(**axis)
+                            .is_some_and(|value| value.abs() > CAPTURE_THRESHOLD)
+                    })
+                    .map(|axis| Binding::Axis(axis.axis_type))
+            });
+            let cursor = self
+                .mouse_buttons
+                .get_just_pressed()
+                .next()
+                .map(|_| Binding::Cursor);
+            return axis.or(cursor);
+        }
+        let key = self
+            .keys
+            .get_just_pressed()
+            .next()
+            .copied()
+            .map(Binding::Key);
+        let mouse = self
+            .mouse_buttons
+            .get_just_pressed()
+            .next()
+            .copied()
+            .map(Binding::Mouse);
+        let gamepad = self
+            .gamepad_buttons
+            .get_just_pressed()
+            .next()
+            .map(|button| Binding::Gamepad(button.button_type));
+        key.or(mouse).or(gamepad)
+    }
+}
+
+/// Digital movement wins over the sticks, which win over the cursor.
+#[allow(clippy::too_many_arguments)]
+fn read_controls(
+    time: Res<Time>,
+    bindings: Res<Bindings>,
+    remapping: Res<Remapping>,
+    devices: InputDevices,
+    mut cursor_aim: ResMut<CursorAim>,
+    mut drop_pressed: ResMut<DropPressed>,
+    platform: Query<(&Transform, &Platform)>,
+    mut input: ResMut<PlatformInput>,
+) {
+    let drop_pressed = std::mem::take(&mut drop_pressed.0);
+    if remapping.open {
+        *input = PlatformInput::default();
+        return;
+    }
+    let pressed = |action| bindings.get(action).iter().any(|b| devices.pressed(*b));
+    let stick = bindings
+        .aim
+        .iter()
+        .map(|binding| devices.axis(*binding, bindings.dead_zone))
+        .find(|value| *value != 0.0);
+
+    input.movement = if pressed(Action::MoveRight) {
+        1.0
+    } else if pressed(Action::MoveLeft) {
+        -1.0
+    } else if let Some(value) = stick {
+        value.clamp(-1.0, 1.0)
+    } else {
+        0.0
+    };
+    if input.movement != 0.0 {
+        cursor_aim.target = None;
+    } else if let (Some(target), Ok((transform, platform))) =
+        (cursor_aim.target, platform.get_single())
+    {
+        // Just enough to reach the target this step.
+        let step = platform.speed * time.delta_seconds();
+        if step > 0.0 {
+            input.movement = ((target - transform.translation.x) / step).clamp(-1.0, 1.0);
+        }
+    }
+    input.drop = drop_pressed || pressed(Action::Drop);
+}
+
+fn latch_drop(
+    bindings: Res<Bindings>,
+    devices: InputDevices,
+    mut drop_pressed: ResMut<DropPressed>,
+) {
+    if bindings.drop.iter().any(|b| devices.just_pressed(*b)) {
+        drop_pressed.0 = true;
+    }
+}
+
+fn toggle_pause(
+    bindings: Res<Bindings>,
+    remapping: Res<Remapping>,
+    devices: InputDevices,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if remapping.open || !bindings.pause.iter().any(|b| devices.just_pressed(*b)) {
+        return;
+    }
+    match state.get() {
+        GameState::Playing => next_state.set(GameState::Paused),
+        GameState::Paused => next_state.set(GameState::Playing),
+        _ => {}
+    }
+}
+
+/// Aims at the point of the arena plane under the cursor whenever it moves.
+fn update_cursor_aim(
+    bindings: Res<Bindings>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut cursor_aim: ResMut<CursorAim>,
+) {
+    let cursor = windows
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position());
+    if cursor == cursor_aim.cursor {
+        return;
+    }
+    cursor_aim.cursor = cursor;
+    if !bindings.aim.contains(&Binding::Cursor) {
+        return;
+    }
+    let (Some(cursor), Ok((camera, camera_transform))) = (cursor, cameras.get_single()) else {
+        return;
+    };
+    let Some(ray) = camera.viewport_to_world(camera_transform, cursor) else {
+        return;
+    };
+    if let Some(distance) = ray.intersect_plane(Vec3::ZERO, Plane3d::new(Vec3::Y)) {
+        cursor_aim.target = Some(ray.get_point(distance).x);
+    }
+}
+
+/// F1 opens the remapping screen, where Up and Down select an action, Enter
+/// binds the next input pressed to it, Backspace clears its bindings and
+/// Delete restores the default ones.
+fn remap_controls(
+    devices: InputDevices,
+    mut remapping: ResMut<Remapping>,
+    mut bindings: ResMut<Bindings>,
+) {
+    let keys = &devices.keys;
+    if remapping.listening {
+        if keys.just_pressed(KeyCode::Escape) {
+            remapping.listening = false;
+        } else if let Some(binding) = devices.capture(remapping.selected) {
+            let action_bindings = bindings.get_mut(remapping.selected);
+            if !action_bindings.contains(&binding) {
+                action_bindings.push(binding);
+            }
+            remapping.listening = false;
+        }
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::F1) || remapping.open && keys.just_pressed(KeyCode::Escape) {
+        remapping.open = !remapping.open;
+    }
+    if !remapping.open {
+        return;
+    }
+    let index = Action::ALL
+        .iter()
+        .position(|action| *action == remapping.selected)
+        .unwrap_or_default();
+    let count = Action::ALL.len();
+    if keys.just_pressed(KeyCode::ArrowUp) {
+        remapping.selected = Action::ALL[(index + count - 1) % count];
+    }
+    if keys.just_pressed(KeyCode::ArrowDown) {
+        remapping.selected = Action::ALL[(index + 1) % count];
+    }
+    if keys.just_pressed(KeyCode::Enter) {
+        remapping.listening = true;
+    }
+    if keys.just_pressed(KeyCode::Backspace) {
+        bindings.get_mut(remapping.selected).clear();
+    }
+    if keys.just_pressed(KeyCode::Delete) {
+        *bindings.get_mut(remapping.selected) = Bindings::default().get(remapping.selected).clone();
+    }
+}
+
+fn save_bindings(bindings: Res<Bindings>, file: Option<Res<BindingsFile>>) {
+    let Some(file) = file else {
+        return;
+    };
+    if !bindings.is_changed() || bindings.is_added() {
+        return;
+    }
+    if let Err(error) = bindings.save(&file.0) {
+        error!("{:?}: {error}", file.0);
+    }
+}
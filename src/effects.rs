@@ -0,0 +1,226 @@
+use bevy::prelude::*;
+use rand::Rng;
+
+use std::f32::consts::TAU;
+
+use crate::{
+    items::ItemsResources,
+    physics::{Ball, MergeEvent},
+    score::Combo,
+    simulation_running,
+};
+
+/// Time in seconds the merged balls take to reach the contact point.
+pub const MERGE_TWEEN_TIME: f32 = 0.1;
+/// Time in seconds a merged ball takes to grow to its full size.
+pub const SCALE_IN_TIME: f32 = 0.25;
+/// Scale a merged ball starts growing from.
+const SCALE_IN_START: f32 = 0.3;
+const PARTICLE_COUNT: usize = 12;
+/// Time in seconds a particle lives.
+pub const PARTICLE_TIME: f32 = 0.5;
+/// Range of the particle speeds, in units of the item radius per second.
+const PARTICLE_SPEED: (f32, f32) = (4.0, 8.0);
+/// Particle size relative to the item radius.
+const PARTICLE_SIZE: f32 = 0.2;
+const PARTICLE_GRAVITY: f32 = 150.0;
+
+/// Animates merges from `MergeEvent`s: the merged balls slide into each
+/// other, the new ball grows in and particles of its colour burst out. The
+/// effects only touch rendering, the physics never sees them. Works without
+/// rendering assets, with default handles.
+pub struct EffectsPlugin;
+
+impl Plugin for EffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, init);
+        // Frozen with the simulation.
+        app.add_systems(
+            Update,
+            (
+                spawn_merge_effects,
+                scale_in_merged_items,
+                merge_tweens_update,
+                scale_ins_update,
+                particles_update,
+            )
+                .chain()
+                .run_if(simulation_running),
+        );
+    }
+}
+
+/// Cubic ease-out, from 0 to 1 as `t` goes from 0 to 1.
+pub fn ease_out(t: f32) -> f32 {
+    1.0 - (1.0 - t.clamp(0.0, 1.0)).powi(3)
+}
+
+#[derive(Resource)]
+pub struct EffectsResources {
+    /// Unit sphere scaled into particles.
+    pub particle_mesh: Handle<Mesh>,
+}
+
+/// Copy of a merged ball sliding into the contact point.
+#[derive(Component, Debug)]
+pub struct MergeTween {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub timer: Timer,
+}
+
+/// Grows a merged ball to its full size. Only the rendered scale changes.
+/// Kept on its own entity, as changing the components of balls outside of
+/// the physics steps would change the order they are simulated in.
+#[derive(Component, Debug)]
+pub struct ScaleIn {
+    pub item: Entity,
+    pub timer: Timer,
+}
+
+#[derive(Component, Debug)]
+pub struct Particle {
+    pub velocity: Vec3,
+    pub size: f32,
+    pub timer: Timer,
+}
+
+fn init(mut commands: Commands, meshes: Option<ResMut<Assets<Mesh>>>) {
+    commands.insert_resource(EffectsResources {
+        particle_mesh: meshes
+            .map(|mut meshes| meshes.add(Sphere { radius: 1.0 }.mesh().build()))
+            .unwrap_or_default(),
+    });
+}
+
+fn spawn_merge_effects(
+    effects_resources: Res<EffectsResources>,
+    items_resources: Res<ItemsResources>,
+    mut materials: Option<ResMut<Assets<StandardMaterial>>>,
+    mut merge_events: EventReader<MergeEvent>,
+    mut commands: Commands,
+) {
+    let mut rng = rand::thread_rng();
+    for event in merge_events.read() {
+        let Some(source) = items_resources.get(event.item_type) else {
+            continue;
+        };
+        for transform in event.sources {
+            commands.spawn((
+                PbrBundle {
+                    mesh: source.mesh.clone(),
+                    material: source.material.clone(),
+                    transform,
+                    ..default()
+                },
+                MergeTween {
+                    start: transform.translation,
+                    end: event.position,
+                    timer: Timer::from_seconds(MERGE_TWEEN_TIME, TimerMode::Once),
+                },
+            ));
+        }
+
+        let tier = event
+            .merged_type
+            .and_then(|item_type| items_resources.get(item_type))
+            .unwrap_or(source);
+        let material = materials
+            .as_mut()
+            .map(|materials| {
+                materials.add(StandardMaterial {
+                    base_color: tier.color,
+                    unlit: true,
+                    ..default()
+                })
+            })
+            .unwrap_or_default();
+        for i in 0..PARTICLE_COUNT {
+            let angle = (i as f32 + rng.gen::<f32>()) / PARTICLE_COUNT as f32 * TAU;
+            let speed = rng.gen_range(PARTICLE_SPEED.0..PARTICLE_SPEED.1) * tier.radius;
+            let direction = Vec2::from_angle(angle);
+            let size = PARTICLE_SIZE * tier.radius;
+            commands.spawn((
+                PbrBundle {
+                    mesh: effects_resources.particle_mesh.clone(),
+                    material: material.clone(),
+                    transform: Transform::from_translation(event.position)
+                        .with_scale(Vec3::splat(size)),
+                    ..default()
+                },
+                Particle {
+                    velocity: Vec3::new(direction.x, 0.0, direction.y) * speed,
+                    size,
+                    timer: Timer::from_seconds(PARTICLE_TIME, TimerMode::Once),
+                },
+            ));
+        }
+    }
+}
+
+/// Items created by a merge are the ones starting a combo.
+fn scale_in_merged_items(
+    mut items: Query<(Entity, &mut Transform, &Combo), Added<Ball>>,
+    mut commands: Commands,
+) {
+    for (entity, mut transform, combo) in items.iter_mut() {
+        if combo.depth > 0 {
+            transform.scale = Vec3::splat(SCALE_IN_START);
+            commands.spawn(ScaleIn {
+                item: entity,
+                timer: Timer::from_seconds(SCALE_IN_TIME, TimerMode::Once),
+            });
+        }
+    }
+}
+
+fn merge_tweens_update(
+    time: Res<Time>,
+    mut tweens: Query<(Entity, &mut Transform, &mut MergeTween)>,
+    mut commands: Commands,
+) {
+    for (entity, mut transform, mut tween) in tweens.iter_mut() {
+        if tween.timer.tick(time.delta()).finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let t = tween.timer.fraction();
+        transform.translation = tween.start.lerp(tween.end, t * t);
+        transform.scale = Vec3::splat(1.0 - t * t);
+    }
+}
+
+fn scale_ins_update(
+    time: Res<Time>,
+    mut scale_ins: Query<(Entity, &mut ScaleIn)>,
+    mut items: Query<&mut Transform, With<Ball>>,
+    mut commands: Commands,
+) {
+    for (entity, mut scale_in) in scale_ins.iter_mut() {
+        let t = ease_out(scale_in.timer.tick(time.delta()).fraction());
+        // The item may have merged again already.
+        if let Ok(mut transform) = items.get_mut(scale_in.item) {
+            transform.scale = Vec3::splat(SCALE_IN_START + (1.0 - SCALE_IN_START) * t);
+        }
+        if scale_in.timer.finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+fn particles_update(
+    time: Res<Time>,
+    mut particles: Query<(Entity, &mut Transform, &mut Particle)>,
+    mut commands: Commands,
+) {
+    let dt = time.delta_seconds();
+    for (entity, mut transform, mut particle) in particles.iter_mut() {
+        if particle.timer.tick(time.delta()).finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        particle.velocity.z -= PARTICLE_GRAVITY * dt;
+        transform.translation += particle.velocity * dt;
+        transform.scale = Vec3::splat(particle.size * (1.0 - particle.timer.fraction()));
+    }
+}
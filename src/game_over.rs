@@ -0,0 +1,155 @@
+use bevy::prelude::*;
+
+use crate::{
+    physics::{Ball, CollisionEvent, PhysicsSystems, Velocity},
+    scene::Level,
+    GameState, RestartEvent, Score,
+};
+
+/// Speed below which a ball above the danger line counts as resting there.
+const RESTING_SPEED: f32 = 5.0;
+
+pub struct GameOverPlugin {
+    /// Height of the danger line above the floor of the box.
+    pub danger_line: f32,
+    /// Time in seconds a ball can rest above the danger line before the game
+    /// is over.
+    pub grace_time: f32,
+}
+
+impl Default for GameOverPlugin {
+    fn default() -> Self {
+        Self {
+            danger_line: 85.0,
+            grace_time: 2.0,
+        }
+    }
+}
+
+impl Plugin for GameOverPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<GameOverEvent>();
+        app.insert_resource(DangerLine {
+            height: self.danger_line,
+            timer: Timer::from_seconds(self.grace_time, TimerMode::Once),
+        });
+        // After the level had a chance to move the line.
+        app.add_systems(PostStartup, spawn_danger_line);
+        app.add_systems(
+            FixedUpdate,
+            mark_landed_balls.in_set(PhysicsSystems::CollisionResolution),
+        );
+        app.add_systems(
+            FixedUpdate,
+            danger_line_system
+                .after(PhysicsSystems::CollisionResolution)
+                .run_if(in_state(GameState::Playing)),
+        );
+        app.add_systems(Update, restart_game.run_if(on_event::<RestartEvent>()));
+    }
+}
+
+/// Sent once when a ball rested above the danger line for the whole grace
+/// time.
+#[derive(Debug, Event)]
+pub struct GameOverEvent {
+    pub score: u32,
+}
+
+/// Marks a ball which touched another ball or a collider. Balls still
+/// falling from the platform start out slow, they only count as resting
+/// above the danger line once they landed.
+#[derive(Component, Debug)]
+pub struct Landed;
+
+#[derive(Debug, Resource)]
+pub struct DangerLine {
+    pub height: f32,
+    /// Runs while any ball rests above the line, reset once none does.
+    pub timer: Timer,
+}
+
+impl DangerLine {
+    pub fn in_danger(&self) -> bool {
+        self.timer.elapsed_secs() > 0.0
+    }
+}
+
+/// The line only gets a mesh when rendering assets exist.
+fn spawn_danger_line(
+    mut commands: Commands,
+    danger_line: Res<DangerLine>,
+    level: Res<Level>,
+    meshes: Option<ResMut<Assets<Mesh>>>,
+    materials: Option<ResMut<Assets<StandardMaterial>>>,
+) {
+    let (Some(mut meshes), Some(mut materials)) = (meshes, materials) else {
+        return;
+    };
+
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(Cuboid::new(level.width, 1.0, 0.5).mesh()),
+        material: materials.add(StandardMaterial {
+            base_color: Color::rgba(1.0, 0.0, 0.0, 0.5),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..default()
+        }),
+        transform: Transform::from_xyz(level.width / 2.0, 0.0, danger_line.height),
+        ..default()
+    });
+}
+
+fn danger_line_system(
+    time: Res<Time>,
+    score: Res<Score>,
+    mut danger_line: ResMut<DangerLine>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut game_over_events: EventWriter<GameOverEvent>,
+    balls: Query<(&Ball, &Transform, &Velocity), With<Landed>>,
+) {
+    let height = danger_line.height;
+    let resting_above = balls.iter().any(|(ball, transform, velocity)| {
+        transform.translation.z + ball.radius > height && velocity.velocity.length() < RESTING_SPEED
+    });
+
+    if !resting_above {
+        danger_line.timer.reset();
+        return;
+    }
+
+    danger_line.timer.tick(time.delta());
+    if danger_line.timer.just_finished() {
+        game_over_events.send(GameOverEvent { score: score.score });
+        next_state.set(GameState::GameOver);
+    }
+}
+
+fn mark_landed_balls(
+    mut commands: Commands,
+    mut collision_events: EventReader<CollisionEvent>,
+    falling: Query<(), (With<Ball>, Without<Landed>)>,
+) {
+    for event in collision_events.read() {
+        for entity in [event.entity1, event.entity2] {
+            if falling.contains(entity) {
+                commands.entity(entity).insert(Landed);
+            }
+        }
+    }
+}
+
+fn restart_game(
+    mut commands: Commands,
+    mut score: ResMut<Score>,
+    mut danger_line: ResMut<DangerLine>,
+    mut next_state: ResMut<NextState<GameState>>,
+    balls: Query<Entity, With<Ball>>,
+) {
+    for entity in balls.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    score.score = 0;
+    danger_line.timer.reset();
+    next_state.set(GameState::Playing);
+}
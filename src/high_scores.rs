@@ -0,0 +1,298 @@
+use bevy::prelude::*;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use std::{
+    cmp::Reverse,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    game_over::GameOverEvent, physics::Ball, randomizer::ItemRandomizer, GameState, RestartEvent,
+};
+
+/// Version of the high score file format. Files of other versions are
+/// treated as corrupt.
+pub const HIGH_SCORES_VERSION: u32 = 1;
+/// Number of runs kept in the table.
+pub const HIGH_SCORE_COUNT: usize = 10;
+pub const INITIALS_LENGTH: usize = 3;
+
+/// Keeps the best runs. Insert `HighScores` before the app starts to start
+/// from saved ones, and a `HighScoresFile` to save new records.
+pub struct HighScoresPlugin;
+
+impl Plugin for HighScoresPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SubmitHighScoreEvent>();
+        app.init_resource::<HighScores>();
+        app.init_resource::<RunStats>();
+        app.init_resource::<PendingHighScore>();
+        app.add_systems(FixedUpdate, track_run.run_if(in_state(GameState::Playing)));
+        // A record left without initials is kept when the next game starts.
+        app.add_systems(
+            Update,
+            (
+                record_run,
+                submit_pending.run_if(on_event::<RestartEvent>()),
+                submit_high_score,
+                reset_run.run_if(on_event::<RestartEvent>()),
+            )
+                .chain(),
+        );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HighScore {
+    pub initials: String,
+    pub score: u32,
+    /// Highest tier of the items created during the run.
+    pub highest_tier: u8,
+    /// Time played in seconds.
+    pub duration: f32,
+    /// Seed of the item sequence.
+    pub seed: u64,
+    /// End of the run, in seconds since the Unix epoch.
+    pub date: u64,
+}
+
+impl HighScore {
+    /// Date of the run as `YYYY-MM-DD`, in UTC.
+    pub fn date_string(&self) -> String {
+        let days = (self.date / 86400) as i64;
+        // Civil date from days since the epoch, after Howard Hinnant.
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let day_of_era = z.rem_euclid(146097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        format!("{year:04}-{month:02}-{day:02}")
+    }
+}
+
+/// Best runs, from the highest score down.
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HighScores {
+    pub version: u32,
+    pub entries: Vec<HighScore>,
+}
+
+impl Default for HighScores {
+    fn default() -> Self {
+        Self {
+            version: HIGH_SCORES_VERSION,
+            entries: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum HighScoresError {
+    #[error("could not access high scores: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse high scores: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("could not write high scores: {0}")]
+    Serialize(#[from] ron::Error),
+    #[error("invalid high scores: {0}")]
+    Invalid(String),
+}
+
+impl HighScores {
+    pub fn from_ron(ron: &str) -> Result<Self, HighScoresError> {
+        let mut high_scores: Self = ron::de::from_str(ron)?;
+        if high_scores.version != HIGH_SCORES_VERSION {
+            return Err(HighScoresError::Invalid(format!(
+                "version {} is not supported, expected {HIGH_SCORES_VERSION}",
+                high_scores.version
+            )));
+        }
+        // Edited files are tidied up rather than rejected.
+        high_scores
+            .entries
+            .sort_by_key(|entry| Reverse(entry.score));
+        high_scores.entries.truncate(HIGH_SCORE_COUNT);
+        Ok(high_scores)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, HighScoresError> {
+        Self::from_ron(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn to_ron(&self) -> Result<String, HighScoresError> {
+        let config = PrettyConfig::default().depth_limit(2);
+        Ok(ron::ser::to_string_pretty(self, config)?)
+    }
+
+    /// Writes a temporary file first, so an interrupted save does not lose
+    /// the previous table.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), HighScoresError> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let temporary = path.with_extension("ron.tmp");
+        std::fs::write(&temporary, self.to_ron()?)?;
+        std::fs::rename(temporary, path)?;
+        Ok(())
+    }
+
+    /// Whether a run with `score` makes it into the table.
+    pub fn qualifies(&self, score: u32) -> bool {
+        score > 0
+            && (self.entries.len() < HIGH_SCORE_COUNT
+                || self.entries.iter().any(|entry| score > entry.score))
+    }
+
+    /// Adds a run, below the runs with the same score. Returns its rank from
+    /// 0, `None` if it did not make it into the table.
+    pub fn insert(&mut self, entry: HighScore) -> Option<usize> {
+        if !self.qualifies(entry.score) {
+            return None;
+        }
+        let rank = self
+            .entries
+            .iter()
+            .position(|other| entry.score > other.score)
+            .unwrap_or(self.entries.len());
+        self.entries.insert(rank, entry);
+        self.entries.truncate(HIGH_SCORE_COUNT);
+        Some(rank)
+    }
+}
+
+/// File the high scores are saved to on every new record.
+#[derive(Resource, Debug, Clone)]
+pub struct HighScoresFile(pub PathBuf);
+
+impl HighScoresFile {
+    /// `high_scores.ron` in the user data directory.
+    pub fn user() -> Option<Self> {
+        crate::data_dir().map(|dir| Self(dir.join("high_scores.ron")))
+    }
+
+    /// Saved high scores, none if the file does not exist yet. Unreadable
+    /// files are moved aside to `.corrupt` so the next save does not
+    /// overwrite them, and the table starts over.
+    pub fn load(&self) -> HighScores {
+        let error = match HighScores::load(&self.0) {
+            Ok(high_scores) => return high_scores,
+            Err(HighScoresError::Io(error)) if error.kind() == std::io::ErrorKind::NotFound => {
+                return HighScores::default();
+            }
+            Err(error) => error,
+        };
+        let corrupt = self.0.with_extension("ron.corrupt");
+        error!("{:?}: {error}, moving it to {corrupt:?}", self.0);
+        if let Err(error) = std::fs::rename(&self.0, &corrupt) {
+            error!("{corrupt:?}: {error}");
+        }
+        HighScores::default()
+    }
+}
+
+/// Progress of the current run.
+#[derive(Resource, Debug, Default)]
+pub struct RunStats {
+    pub highest_tier: u8,
+    /// Time played in seconds.
+    pub duration: f32,
+}
+
+/// Record of the last run, waiting for the player's initials.
+#[derive(Resource, Debug, Default)]
+pub struct PendingHighScore {
+    pub entry: Option<HighScore>,
+    /// Rank of the last submitted record, to highlight it in the table.
+    pub last_rank: Option<usize>,
+}
+
+/// Adds the pending record to the table with the given initials.
+#[derive(Debug, Event)]
+pub struct SubmitHighScoreEvent {
+    pub initials: String,
+}
+
+fn track_run(time: Res<Time>, balls: Query<&Ball>, mut stats: ResMut<RunStats>) {
+    stats.duration += time.delta_seconds();
+    let highest_tier = balls.iter().map(|ball| ball.ball_type).max();
+    stats.highest_tier = stats.highest_tier.max(highest_tier.unwrap_or_default());
+}
+
+fn reset_run(mut stats: ResMut<RunStats>) {
+    *stats = RunStats::default();
+}
+
+fn record_run(
+    stats: Res<RunStats>,
+    high_scores: Res<HighScores>,
+    randomizer: Res<ItemRandomizer>,
+    mut pending: ResMut<PendingHighScore>,
+    mut game_over_events: EventReader<GameOverEvent>,
+) {
+    for event in game_over_events.read() {
+        pending.last_rank = None;
+        if !high_scores.qualifies(event.score) {
+            continue;
+        }
+        let date = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        pending.entry = Some(HighScore {
+            initials: String::new(),
+            score: event.score,
+            highest_tier: stats.highest_tier,
+            duration: stats.duration,
+            seed: randomizer.seed,
+            date,
+        });
+    }
+}
+
+fn submit_pending(
+    pending: Res<PendingHighScore>,
+    mut submit_events: EventWriter<SubmitHighScoreEvent>,
+) {
+    if pending.entry.is_some() {
+        submit_events.send(SubmitHighScoreEvent {
+            initials: String::new(),
+        });
+    }
+}
+
+fn submit_high_score(
+    file: Option<Res<HighScoresFile>>,
+    mut high_scores: ResMut<HighScores>,
+    mut pending: ResMut<PendingHighScore>,
+    mut submit_events: EventReader<SubmitHighScoreEvent>,
+) {
+    for event in submit_events.read() {
+        let Some(mut entry) = pending.entry.take() else {
+            continue;
+        };
+        entry.initials = event
+            .initials
+            .trim()
+            .chars()
+            .take(INITIALS_LENGTH)
+            .collect::<String>()
+            .to_uppercase();
+        if entry.initials.is_empty() {
+            entry.initials = "???".to_string();
+        }
+        pending.last_rank = high_scores.insert(entry);
+        if let Some(file) = &file {
+            if let Err(error) = high_scores.save(&file.0) {
+                error!("{:?}: {error}", file.0);
+            }
+        }
+    }
+}
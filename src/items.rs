@@ -0,0 +1,338 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::physics::{Ball, SleepTimer, Sleeping};
+
+/// Tiers used until `ITEMS_PATH` is loaded, and when the game runs without an
+/// `AssetServer`.
+const DEFAULT_ITEMS: &str = include_str!("../assets/items.ron");
+pub const ITEMS_PATH: &str = "items.ron";
+
+pub struct ItemsPlugin;
+
+impl Plugin for ItemsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, init);
+
+        if app.world.contains_resource::<AssetServer>() {
+            app.init_asset::<ItemTiers>();
+            app.register_asset_loader(ItemTiersLoader);
+            app.add_systems(Startup, load_item_tiers);
+            app.add_systems(Update, reload_item_tiers);
+        }
+    }
+}
+
+/// Definition of a single tier of items.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ItemTier {
+    pub radius: f32,
+    pub bounciness: f32,
+    pub friction: f32,
+    /// Hex colour, like the ones accepted by `Color::hex`.
+    pub color: String,
+    /// Asset path of the mesh, a sphere of `radius` if not set.
+    #[serde(default)]
+    pub mesh: Option<String>,
+    /// Asset path of the base colour texture.
+    #[serde(default)]
+    pub texture: Option<String>,
+    /// Points for creating an item of this tier by a merge, multiplied by the
+    /// combo depth.
+    pub score: u32,
+    /// Relative chance of the platform dropping an item of this tier.
+    #[serde(default)]
+    pub spawn_weight: f32,
+}
+
+#[derive(Asset, TypePath, Debug, Clone, Deserialize)]
+pub struct ItemTiers {
+    /// Tiers from the smallest to the top one.
+    pub tiers: Vec<ItemTier>,
+    /// Item two top tier items merge into with `TopTierMerge::FinalItem`.
+    #[serde(default)]
+    pub final_item: Option<ItemTier>,
+}
+
+#[derive(Debug, Error)]
+pub enum ItemTiersError {
+    #[error("could not read item tiers: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse item tiers: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("invalid item tiers: {0}")]
+    Invalid(String),
+}
+
+impl ItemTiers {
+    pub fn from_ron(ron: &str) -> Result<Self, ItemTiersError> {
+        let tiers: Self = ron::de::from_str(ron)?;
+        tiers.validate()?;
+        Ok(tiers)
+    }
+
+    fn validate(&self) -> Result<(), ItemTiersError> {
+        let invalid = |message: String| Err(ItemTiersError::Invalid(message));
+
+        if self.tiers.is_empty() {
+            return invalid("there are no tiers".into());
+        }
+        // Item types are `u8`s and the final item comes after the tiers.
+        if self.tiers.len() >= u8::MAX as usize {
+            return invalid(format!(
+                "{} tiers, at most 254 are allowed",
+                self.tiers.len()
+            ));
+        }
+        for (i, tier) in self.tiers.iter().chain(&self.final_item).enumerate() {
+            if !tier.radius.is_finite() || tier.radius <= 0.0 {
+                return invalid(format!("tier {i} has a radius of {}", tier.radius));
+            }
+            if !tier.spawn_weight.is_finite() || tier.spawn_weight < 0.0 {
+                return invalid(format!(
+                    "tier {i} has a spawn weight of {}",
+                    tier.spawn_weight
+                ));
+            }
+            if Color::hex(&tier.color).is_err() {
+                return invalid(format!("tier {i} has an invalid color {:?}", tier.color));
+            }
+        }
+        if self.tiers.iter().all(|tier| tier.spawn_weight == 0.0) {
+            return invalid("no tier has a positive spawn weight".into());
+        }
+        Ok(())
+    }
+}
+
+impl Default for ItemTiers {
+    fn default() -> Self {
+        Self::from_ron(DEFAULT_ITEMS).expect("bundled item tiers are valid")
+    }
+}
+
+#[derive(Default)]
+pub struct ItemTiersLoader;
+
+impl AssetLoader for ItemTiersLoader {
+    type Asset = ItemTiers;
+    type Settings = ();
+    type Error = ItemTiersError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut ron = String::new();
+            reader.read_to_string(&mut ron).await?;
+            ItemTiers::from_ron(&ron)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["items.ron"]
+    }
+}
+
+pub struct ItemResource {
+    pub mesh: Handle<Mesh>,
+    pub material: Handle<StandardMaterial>,
+    pub color: Color,
+    pub radius: f32,
+    pub bounciness: f32,
+    pub friction: f32,
+    pub score: u32,
+    pub spawn_weight: f32,
+}
+
+#[derive(Resource)]
+pub struct ItemsResources {
+    /// Tiers from the smallest to the top one.
+    pub tiers: Vec<ItemResource>,
+    /// Item two top tier items merge into with `TopTierMerge::FinalItem`.
+    pub final_item: Option<ItemResource>,
+    spawn_weights: WeightedIndex<f32>,
+}
+
+impl ItemsResources {
+    /// Rendering assets are optional so items can be created headless, in
+    /// which case they get default handles.
+    pub fn new(
+        tiers: &ItemTiers,
+        mut meshes: Option<&mut Assets<Mesh>>,
+        mut materials: Option<&mut Assets<StandardMaterial>>,
+        asset_server: Option<&AssetServer>,
+    ) -> Self {
+        let mut item_resource = |tier: &ItemTier| {
+            let color = Color::hex(&tier.color).unwrap_or(Color::WHITE);
+            ItemResource {
+                mesh: match (&tier.mesh, asset_server, meshes.as_mut()) {
+                    (Some(path), Some(asset_server), _) => asset_server.load(path),
+                    (_, _, Some(meshes)) => meshes.add(
+                        Sphere {
+                            radius: tier.radius,
+                        }
+                        .mesh()
+                        .build(),
+                    ),
+                    _ => Handle::default(),
+                },
+                material: materials
+                    .as_mut()
+                    .map(|materials| {
+                        materials.add(StandardMaterial {
+                            base_color: color,
+                            base_color_texture: tier
+                                .texture
+                                .as_ref()
+                                .zip(asset_server)
+                                .map(|(path, asset_server)| asset_server.load(path)),
+                            ..default()
+                        })
+                    })
+                    .unwrap_or_default(),
+                color,
+                radius: tier.radius,
+                bounciness: tier.bounciness,
+                friction: tier.friction,
+                score: tier.score,
+                spawn_weight: tier.spawn_weight,
+            }
+        };
+
+        let resources = tiers.tiers.iter().map(&mut item_resource).collect();
+        let final_item = tiers.final_item.as_ref().map(&mut item_resource);
+        let spawn_weights = WeightedIndex::new(tiers.tiers.iter().map(|tier| tier.spawn_weight))
+            .expect("item tiers are validated");
+        Self {
+            tiers: resources,
+            final_item,
+            spawn_weights,
+        }
+    }
+
+    /// Type of the item created with `TopTierMerge::FinalItem`.
+    pub fn final_item_type(&self) -> u8 {
+        self.tiers.len() as u8
+    }
+
+    pub fn get(&self, item_type: u8) -> Option<&ItemResource> {
+        let item_type = item_type as usize;
+        if item_type == self.tiers.len() {
+            self.final_item.as_ref()
+        } else {
+            self.tiers.get(item_type)
+        }
+    }
+
+    /// Tiers with a positive spawn weight.
+    pub fn droppable_tiers(&self) -> impl Iterator<Item = u8> + '_ {
+        self.tiers
+            .iter()
+            .enumerate()
+            .filter(|(_, tier)| tier.spawn_weight > 0.0)
+            .map(|(item_type, _)| item_type as u8)
+    }
+
+    /// Picks a tier to drop according to the spawn weights.
+    pub fn random_tier(&self, rng: &mut impl Rng) -> u8 {
+        self.spawn_weights.sample(rng) as u8
+    }
+}
+
+/// Handle keeping the loaded `ItemTiers` alive.
+#[derive(Resource)]
+pub struct ItemTiersHandle(pub Handle<ItemTiers>);
+
+fn init(
+    mut commands: Commands,
+    mut meshes: Option<ResMut<Assets<Mesh>>>,
+    mut materials: Option<ResMut<Assets<StandardMaterial>>>,
+    asset_server: Option<Res<AssetServer>>,
+) {
+    commands.insert_resource(ItemsResources::new(
+        &ItemTiers::default(),
+        meshes.as_deref_mut(),
+        materials.as_deref_mut(),
+        asset_server.as_deref(),
+    ));
+}
+
+fn load_item_tiers(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(ItemTiersHandle(asset_server.load(ITEMS_PATH)));
+}
+
+/// Swaps in new tiers once the file is loaded or changes on disk, and updates
+/// the items already in the box.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn reload_item_tiers(
+    item_tiers: Res<Assets<ItemTiers>>,
+    item_tiers_handle: Res<ItemTiersHandle>,
+    asset_server: Res<AssetServer>,
+    mut meshes: Option<ResMut<Assets<Mesh>>>,
+    mut materials: Option<ResMut<Assets<StandardMaterial>>>,
+    mut items_resources: ResMut<ItemsResources>,
+    mut asset_events: EventReader<AssetEvent<ItemTiers>>,
+    mut balls: Query<(
+        Entity,
+        &mut Ball,
+        Option<&mut Handle<Mesh>>,
+        Option<&mut Handle<StandardMaterial>>,
+    )>,
+    mut commands: Commands,
+) {
+    let id = item_tiers_handle.0.id();
+    let changed = asset_events
+        .read()
+        .filter(|event| event.is_added(id) || event.is_modified(id))
+        .count()
+        > 0;
+    if !changed {
+        return;
+    }
+    let Some(tiers) = item_tiers.get(id) else {
+        return;
+    };
+
+    *items_resources = ItemsResources::new(
+        tiers,
+        meshes.as_deref_mut(),
+        materials.as_deref_mut(),
+        Some(&asset_server),
+    );
+    info!("loaded {} item tiers", items_resources.tiers.len());
+
+    for (entity, mut ball, mesh, material) in balls.iter_mut() {
+        let Some(resources) = items_resources.get(ball.ball_type) else {
+            warn!("item of type {} has no tier anymore", ball.ball_type);
+            continue;
+        };
+        let changed = (ball.radius, ball.bounciness, ball.friction)
+            != (resources.radius, resources.bounciness, resources.friction);
+        ball.radius = resources.radius;
+        ball.bounciness = resources.bounciness;
+        ball.friction = resources.friction;
+        // Sleeping balls would keep overlapping or floating otherwise.
+        if changed {
+            commands
+                .entity(entity)
+                .remove::<Sleeping>()
+                .insert(SleepTimer::default());
+        }
+        if let Some(mut mesh) = mesh {
+            *mesh = resources.mesh.clone();
+        }
+        if let Some(mut material) = material {
+            *material = resources.material.clone();
+        }
+    }
+}
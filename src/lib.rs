@@ -0,0 +1,130 @@
+use std::{path::PathBuf, time::Duration};
+
+use bevy::{input::InputPlugin, prelude::*, time::TimeUpdateStrategy};
+
+pub mod controls;
+pub mod effects;
+pub mod game_over;
+pub mod high_scores;
+pub mod items;
+pub mod physics;
+pub mod platform;
+pub mod randomizer;
+pub mod replay;
+pub mod save;
+pub mod scene;
+pub mod score;
+pub mod ui;
+
+use controls::ControlsPlugin;
+use effects::EffectsPlugin;
+use game_over::GameOverPlugin;
+use high_scores::HighScoresPlugin;
+use items::ItemsPlugin;
+use physics::PhysicsPlugin;
+use platform::PlatformPlugin;
+use save::SavePlugin;
+use scene::ScenePlugin;
+use score::ScorePlugin;
+
+#[derive(Default, Resource)]
+pub struct Score {
+    pub score: u32,
+}
+
+/// Headless apps start playing right away, the windowed one in the main
+/// menu.
+#[derive(States, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameState {
+    MainMenu,
+    #[default]
+    Playing,
+    Paused,
+    GameOver,
+}
+
+impl GameState {
+    /// Whether the physics advances. Items keep settling after the game is
+    /// over.
+    pub fn is_running(self) -> bool {
+        matches!(self, GameState::Playing | GameState::GameOver)
+    }
+}
+
+/// Run condition of the systems advancing the simulation. Apps without a
+/// `GameState` always run them.
+pub fn simulation_running(state: Option<Res<State<GameState>>>) -> bool {
+    state.is_none_or(|state| state.is_running())
+}
+
+/// Starts a new game: items are cleared, the score and the platform reset
+/// and the level spawned again.
+#[derive(Debug, Default, Event)]
+pub struct RestartEvent;
+
+/// Directory of the user's settings, following the platform conventions
+/// without the help of a crate.
+pub fn config_dir() -> Option<PathBuf> {
+    let dir = if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME") {
+        PathBuf::from(dir)
+    } else if let Some(dir) = std::env::var_os("APPDATA") {
+        PathBuf::from(dir)
+    } else {
+        PathBuf::from(std::env::var_os("HOME")?).join(".config")
+    };
+    Some(dir.join("combobox"))
+}
+
+/// Directory of the files the game keeps for the user, like `config_dir`.
+pub fn data_dir() -> Option<PathBuf> {
+    let dir = if let Some(dir) = std::env::var_os("XDG_DATA_HOME") {
+        PathBuf::from(dir)
+    } else if let Some(dir) = std::env::var_os("APPDATA") {
+        PathBuf::from(dir)
+    } else {
+        PathBuf::from(std::env::var_os("HOME")?).join(".local/share")
+    };
+    Some(dir.join("combobox"))
+}
+
+/// Adds the game logic shared by the windowed and the headless apps.
+pub fn add_game_plugins(app: &mut App, physics: PhysicsPlugin, scene: ScenePlugin) {
+    app.add_plugins(physics);
+    app.add_plugins(ItemsPlugin);
+    app.add_plugins(PlatformPlugin);
+    app.add_plugins(ControlsPlugin);
+    app.add_plugins(scene);
+    app.add_plugins(GameOverPlugin::default());
+    app.add_plugins(ScorePlugin);
+    app.add_plugins(HighScoresPlugin);
+    app.add_plugins(SavePlugin);
+    app.add_plugins(EffectsPlugin);
+    app.init_resource::<Score>();
+    app.init_state::<GameState>();
+    app.add_event::<RestartEvent>();
+}
+
+/// Builds the game on top of `MinimalPlugins`, without a window or any
+/// rendering assets. Every update advances the clock by exactly one physics
+/// tick, so runs are as fast as the machine allows and reproducible.
+pub fn headless_app(tick_rate: f64) -> App {
+    headless_app_with(
+        PhysicsPlugin {
+            tick_rate,
+            ..default()
+        },
+        ScenePlugin::default(),
+    )
+}
+
+/// Same as `headless_app`, with custom physics and level.
+pub fn headless_app_with(physics: PhysicsPlugin, scene: ScenePlugin) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins);
+    app.add_plugins(InputPlugin);
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+        1.0 / physics.tick_rate,
+    )));
+    add_game_plugins(&mut app, physics, scene);
+    app
+}
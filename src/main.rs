@@ -1,46 +1,229 @@
 use bevy::prelude::*;
 
-mod physics;
-mod platform;
-mod scene;
-mod ui;
+use combobox::{
+    add_game_plugins,
+    controls::BindingsFile,
+    headless_app_with,
+    high_scores::HighScoresFile,
+    physics::{PhysicsPlugin, TopTierMerge},
+    randomizer::{ItemRandomizer, Randomizer},
+    replay::{Replay, ReplayMode, ReplayPlugin, ReplayRecorder},
+    save::SaveFile,
+    scene::{Level, ScenePlugin},
+    ui::HudPlugin,
+    GameState, Score,
+};
 
-use physics::PhysicsPlugin;
-use platform::PlatformPlugin;
-use scene::ScenePlugin;
-use ui::HudPlugin;
+const TICK_RATE: f64 = 64.0;
 
 fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut headless = false;
+    let mut frames = None;
+    let mut level = Level::default();
+    let mut seed = None;
+    let mut randomizer = Randomizer::default();
+    let mut replay_mode = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--headless" => headless = true,
+            "--frames" => {
+                frames = args.next().and_then(|frames| frames.parse::<u32>().ok());
+                if frames.is_none() {
+                    eprintln!("--frames expects a number of frames");
+                    std::process::exit(2);
+                }
+            }
+            "--level" => {
+                let Some(path) = args.next() else {
+                    eprintln!("--level expects a level file");
+                    std::process::exit(2);
+                };
+                level = match Level::load(&path) {
+                    Ok(level) => level,
+                    Err(error) => {
+                        eprintln!("{path}: {error}");
+                        std::process::exit(1);
+                    }
+                };
+            }
+            "--seed" => {
+                seed = args.next().and_then(|seed| seed.parse::<u64>().ok());
+                if seed.is_none() {
+                    eprintln!("--seed expects a number");
+                    std::process::exit(2);
+                }
+            }
+            "--randomizer" => {
+                randomizer = match args.next().as_deref() {
+                    Some("uniform") => Randomizer::Uniform,
+                    Some("weighted") => Randomizer::Weighted,
+                    Some("bag") => Randomizer::Bag,
+                    _ => {
+                        eprintln!("--randomizer expects uniform, weighted or bag");
+                        std::process::exit(2);
+                    }
+                };
+            }
+            "--record" => {
+                let Some(path) = args.next() else {
+                    eprintln!("--record expects a replay file");
+                    std::process::exit(2);
+                };
+                replay_mode = Some(ReplayMode::Record(path.into()));
+            }
+            "--replay" => {
+                let Some(path) = args.next() else {
+                    eprintln!("--replay expects a replay file");
+                    std::process::exit(2);
+                };
+                match Replay::load(&path) {
+                    Ok(replay) => replay_mode = Some(ReplayMode::Play(replay)),
+                    Err(error) => {
+                        eprintln!("{path}: {error}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            _ => {
+                eprintln!(
+                    "usage: combobox [--level <file>] [--seed <number>] \
+                     [--randomizer uniform|weighted|bag] [--record <file> | --replay <file>] \
+                     [--headless [--frames <count>]]"
+                );
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let top_tier_merge = TopTierMerge::Vanish { bonus: 100 };
+    if let Some(ReplayMode::Play(replay)) = &replay_mode {
+        if let Err(error) = replay.check(&level, top_tier_merge) {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    }
+    let physics = PhysicsPlugin {
+        debug: false,
+        tick_rate: match &replay_mode {
+            Some(ReplayMode::Play(replay)) => replay.tick_rate,
+            _ => TICK_RATE,
+        },
+        top_tier_merge,
+    };
+    let scene = ScenePlugin { level };
+    let randomizer = ItemRandomizer::new(randomizer, seed.unwrap_or_else(rand::random));
+    // A replay replaces the randomizer with the recorded one.
+    let replay = replay_mode.map(|mode| ReplayPlugin { mode });
+    if headless {
+        run_headless(physics, scene, randomizer, replay, frames);
+    } else {
+        run_windowed(physics, scene, randomizer, replay);
+    }
+}
+
+/// Runs the simulation without a window, either until killed or for the given
+/// number of frames, printing the final score. Replays run to their end by
+/// default.
+fn run_headless(
+    physics: PhysicsPlugin,
+    scene: ScenePlugin,
+    randomizer: ItemRandomizer,
+    replay: Option<ReplayPlugin>,
+    frames: Option<u32>,
+) {
+    let mut app = headless_app_with(physics, scene);
+    app.insert_resource(randomizer);
+    let frames = match &replay {
+        Some(ReplayPlugin {
+            mode: ReplayMode::Play(replay),
+        }) => frames.or(Some(replay.len())),
+        _ => frames,
+    };
+    if let Some(replay) = replay {
+        app.add_plugins(replay);
+    }
+    match frames {
+        Some(frames) => {
+            for _ in 0..frames {
+                app.update();
+            }
+            println!("Score: {}", app.world.resource::<Score>().score);
+            if let Some(recorder) = app.world.get_resource::<ReplayRecorder>() {
+                if let Err(error) = recorder.save() {
+                    eprintln!("{:?}: {error}", recorder.path);
+                }
+            }
+        }
+        None => app.run(),
+    }
+}
+
+fn run_windowed(
+    physics: PhysicsPlugin,
+    scene: ScenePlugin,
+    randomizer: ItemRandomizer,
+    replay: Option<ReplayPlugin>,
+) {
     let mut app = App::new();
+    app.insert_resource(randomizer);
 
     app.insert_resource(AmbientLight {
         color: Color::WHITE,
         brightness: 20.0,
     });
 
+    // Headless runs keep the default bindings and no high scores, so they do
+    // not depend on the user's files.
+    if let Some(file) = BindingsFile::user() {
+        match file.load() {
+            Ok(bindings) => {
+                app.insert_resource(bindings);
+            }
+            Err(error) => eprintln!("{:?}: {error}", file.0),
+        }
+        app.insert_resource(file);
+    }
+    if let Some(file) = HighScoresFile::user() {
+        app.insert_resource(file.load());
+        app.insert_resource(file);
+    }
+
+    // Replays start right away, since a restart would not be replayed, and
+    // are not saved.
+    if replay.is_none() {
+        let snapshot = SaveFile::user().and_then(|file| {
+            let snapshot = file.load(&scene.level);
+            app.insert_resource(file);
+            snapshot
+        });
+        // A saved game resumes paused.
+        if let Some(snapshot) = snapshot {
+            app.insert_resource(snapshot);
+            app.insert_state(GameState::Paused);
+        } else {
+            app.insert_state(GameState::MainMenu);
+        }
+    }
+
     app.add_plugins(DefaultPlugins);
-    app.add_plugins(PhysicsPlugin { debug: false });
-    app.add_plugins(PlatformPlugin);
-    app.add_plugins(ScenePlugin);
+    add_game_plugins(&mut app, physics, scene);
     app.add_plugins(HudPlugin);
+    if let Some(replay) = replay {
+        app.add_plugins(replay);
+    }
 
     app.add_systems(Startup, setup);
 
     app.run();
 }
 
-#[derive(Default, Resource)]
-pub struct Score {
-    score: u32,
-}
-
 fn setup(
+    level: Res<Level>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
-    commands.insert_resource(Score::default());
-
     // light
     commands.spawn(PointLightBundle {
         point_light: PointLight {
@@ -48,13 +231,20 @@ fn setup(
             range: 1000.0,
             ..default()
         },
-        transform: Transform::from_xyz(50.0, -50.0, 80.0),
+        transform: Transform::from_xyz(level.width / 2.0, -50.0, level.height * 0.8),
         ..default()
     });
     // camera
     commands.spawn(Camera3dBundle {
-        transform: Transform::from_xyz(50.0, -200.0, 100.0)
-            .looking_at(Vec3::new(50.0, 0.0, 40.0), Vec3::Z),
+        transform: Transform::from_xyz(
+            level.width / 2.0,
+            -2.0 * level.width.max(level.height),
+            level.height,
+        )
+        .looking_at(
+            Vec3::new(level.width / 2.0, 0.0, level.height * 0.4),
+            Vec3::Z,
+        ),
         ..default()
     });
 
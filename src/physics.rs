@@ -1,12 +1,39 @@
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, math::Affine2, prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    platform::{SpawnItemEvent, NUM_ITEMS},
-    Score,
+    items::ItemsResources,
+    platform::SpawnItemEvent,
+    score::{merge_depth, Combo, ScoreEvent},
+    simulation_running, Score,
 };
 
 const GRAVITY: f32 = 200.0;
 const MAX_SPEED: f32 = 100.0;
+const SOLVER_VELOCITY_ITERATIONS: usize = 8;
+const SOLVER_POSITION_ITERATIONS: usize = 4;
+/// Normal speed below which contacts stop bouncing, so resting balls settle.
+const RESTING_SPEED: f32 = 10.0;
+/// Penetration depth left uncorrected to keep resting contacts alive.
+const PENETRATION_SLOP: f32 = 0.05;
+/// Fraction of the penetration removed by each position iteration.
+const POSITION_CORRECTION: f32 = 0.8;
+/// Fraction of the angular velocity lost per second, so rolling balls stop.
+const ANGULAR_DAMPING: f32 = 2.0;
+/// Speed of the center and of the surface below which a ball is considered
+/// to be resting.
+const SLEEP_SPEED: f32 = 2.0;
+/// Time in seconds a ball has to rest before it falls asleep.
+const SLEEP_TIME: f32 = 0.5;
+/// Maximum number of wall impacts handled during a single move.
+const SWEEP_ITERATIONS: usize = 3;
+/// Fraction of the radius a move has to penetrate a wall by to be stopped at
+/// the impact instead of being resolved as a regular contact.
+const SWEEP_DEPTH: f32 = 0.5;
+/// Maximum number of conservative advancement steps of a single sweep.
+const SWEEP_STEPS: usize = 16;
+/// Distance to a wall at which a sweep considers it hit.
+const SWEEP_TOLERANCE: f32 = 0.01;
 
 #[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PhysicsSystems {
@@ -16,39 +43,132 @@ pub enum PhysicsSystems {
 }
 
 pub struct PhysicsPlugin {
+    /// Starts with the `PhysicsDebug` overlay enabled.
     pub debug: bool,
+    /// Number of physics steps per second.
+    pub tick_rate: f64,
+    pub top_tier_merge: TopTierMerge,
+}
+
+impl Default for PhysicsPlugin {
+    fn default() -> Self {
+        Self {
+            debug: false,
+            tick_rate: 64.0,
+            top_tier_merge: TopTierMerge::default(),
+        }
+    }
 }
 
 impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<CollisionEvent>();
+        app.add_event::<MergeEvent>();
+        app.insert_resource(self.top_tier_merge);
+        app.init_resource::<BroadPhase>();
+        app.insert_resource(PhysicsDebug {
+            enabled: self.debug,
+            ..default()
+        });
+        app.insert_resource(Time::<Fixed>::from_hz(self.tick_rate));
 
         app.configure_sets(
-            Update,
+            FixedUpdate,
             PhysicsSystems::Movement.before(PhysicsSystems::CollisionDetection),
         );
         app.configure_sets(
-            Update,
+            FixedUpdate,
             PhysicsSystems::CollisionDetection.before(PhysicsSystems::CollisionResolution),
         );
+        // Frozen in the menus and while paused.
+        for set in [
+            PhysicsSystems::Movement,
+            PhysicsSystems::CollisionDetection,
+            PhysicsSystems::CollisionResolution,
+        ] {
+            app.configure_sets(FixedUpdate, set.run_if(simulation_running));
+        }
 
-        app.add_systems(Update, balls_update.in_set(PhysicsSystems::Movement));
         app.add_systems(
-            Update,
-            (ball_rect_collision_system, ball_ball_collision_system)
+            FixedUpdate,
+            (restore_physics_transforms, balls_update)
+                .chain()
+                .in_set(PhysicsSystems::Movement),
+        );
+        app.add_systems(
+            FixedUpdate,
+            (
+                update_broad_phase,
+                ball_ball_collision_system,
+                ball_collider_collision_system,
+            )
+                .chain()
                 .in_set(PhysicsSystems::CollisionDetection),
         );
         app.add_systems(
-            Update,
-            balls_collision_resolution.in_set(PhysicsSystems::CollisionResolution),
+            FixedUpdate,
+            (
+                balls_collision_resolution,
+                update_sleeping,
+                store_physics_transforms,
+            )
+                .chain()
+                .in_set(PhysicsSystems::CollisionResolution),
+        );
+        app.add_systems(
+            FixedUpdate,
+            record_debug_contacts.in_set(PhysicsSystems::CollisionResolution),
         );
+        // `previous` is not reset while paused, moving balls would jitter.
+        app.add_systems(Update, interpolate_transforms.run_if(simulation_running));
+    }
+}
+
+/// What happens when two balls of the highest tier touch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Resource, Serialize, Deserialize)]
+pub enum TopTierMerge {
+    /// Both balls disappear and the score grows by `bonus`, times the combo
+    /// depth.
+    Vanish { bonus: u32 },
+    /// Both balls merge into a `FINAL_ITEM`, which never merges again.
+    FinalItem,
+    /// The balls collide like balls of different types.
+    Refuse,
+}
 
-        if self.debug {
-            app.add_systems(
-                Update,
-                debug_physics_event.in_set(PhysicsSystems::CollisionResolution),
-            );
-            app.add_systems(Update, debug_physics_rect);
+impl Default for TopTierMerge {
+    fn default() -> Self {
+        Self::Vanish { bonus: 100 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MergeOutcome {
+    Item { item_type: u8, score: u32 },
+    Vanish { bonus: u32 },
+}
+
+impl TopTierMerge {
+    /// Result of merging two balls of `ball_type`, if they merge at all.
+    fn merge(&self, ball_type: u8, items: &ItemsResources) -> Option<MergeOutcome> {
+        // Final items and balls without a tier never merge.
+        items.tiers.get(ball_type as usize)?;
+        if let Some(tier) = items.tiers.get(ball_type as usize + 1) {
+            return Some(MergeOutcome::Item {
+                item_type: ball_type + 1,
+                score: tier.score,
+            });
+        }
+        match self {
+            Self::Vanish { bonus } => Some(MergeOutcome::Vanish { bonus: *bonus }),
+            Self::FinalItem => items
+                .final_item
+                .as_ref()
+                .map(|final_item| MergeOutcome::Item {
+                    item_type: items.final_item_type(),
+                    score: final_item.score,
+                }),
+            Self::Refuse => None,
         }
     }
 }
@@ -58,111 +178,866 @@ pub struct Velocity {
     pub velocity: Vec3,
 }
 
-#[derive(Component, Debug)]
+/// Rotation speed in radians per second, counterclockwise in the XZ plane
+/// (which is a clockwise rotation around the Y axis).
+#[derive(Component, Debug, Default)]
+pub struct AngularVelocity {
+    pub velocity: f32,
+}
+
+#[derive(Component, Debug, Clone, Copy)]
 pub struct Ball {
     pub radius: f32,
     pub bounciness: f32,
+    pub friction: f32,
     pub ball_type: u8,
 }
 
-#[derive(Component, Debug)]
-pub struct Rectangle {
-    pub width: f32,
-    pub height: f32,
+impl Ball {
+    /// Mass of a solid sphere with unit density.
+    pub fn mass(&self) -> f32 {
+        4.0 / 3.0 * std::f32::consts::PI * self.radius.powi(3)
+    }
+
+    /// Moment of inertia of a solid sphere.
+    pub fn inertia(&self) -> f32 {
+        2.0 / 5.0 * self.mass() * self.radius.powi(2)
+    }
+}
+
+/// Static collider shape in the local space of the entity `Transform`.
+/// Colliders live in the XZ plane, so only the rotation around the Y axis is
+/// taken into account, with local X mapped to world X for no rotation.
+#[derive(Component, Debug, Clone, PartialEq)]
+pub enum Collider {
+    /// Rectangle with `width` along local X and `height` along local Z.
+    Rectangle {
+        width: f32,
+        height: f32,
+    },
+    Circle {
+        radius: f32,
+    },
+    /// Convex polygon with points going counterclockwise (X right, Z up).
+    ConvexPolygon {
+        points: Vec<Vec2>,
+    },
+    /// Segment along local X with rounded ends of `radius`. A zero radius
+    /// makes it a plain line segment.
+    Capsule {
+        half_length: f32,
+        radius: f32,
+    },
+}
+
+/// Closest point on a collider boundary with the outward normal there.
+#[derive(Debug, Clone, Copy)]
+struct SurfacePoint {
+    point: Vec2,
+    normal: Vec2,
+    /// Whether the queried point is inside of the collider.
+    inside: bool,
+}
+
+impl Collider {
+    /// Closed boundary in local space, with curves split into `segments`
+    /// per full turn.
+    pub fn outline(&self, segments: usize) -> Vec<Vec2> {
+        let arc = |center: Vec2, radius: f32, start: f32, turn: f32| {
+            let count = ((segments as f32 * turn).ceil() as usize).max(1);
+            (0..=count).map(move |i| {
+                let angle = (start + turn * i as f32 / count as f32) * std::f32::consts::TAU;
+                center + Vec2::from_angle(angle) * radius
+            })
+        };
+        match self {
+            Collider::Rectangle { width, height } => {
+                let half_size = Vec2::new(*width, *height) / 2.0;
+                vec![
+                    -half_size,
+                    Vec2::new(half_size.x, -half_size.y),
+                    half_size,
+                    Vec2::new(-half_size.x, half_size.y),
+                ]
+            }
+            Collider::Circle { radius } => arc(Vec2::ZERO, *radius, 0.0, 1.0).collect(),
+            Collider::ConvexPolygon { points } => points.clone(),
+            Collider::Capsule {
+                half_length,
+                radius,
+            } => arc(Vec2::new(*half_length, 0.0), *radius, -0.25, 0.5)
+                .chain(arc(Vec2::new(-half_length, 0.0), *radius, 0.25, 0.5))
+                .collect(),
+        }
+    }
+
+    /// Bounding box in local space.
+    pub fn local_aabb(&self) -> (Vec2, Vec2) {
+        match self {
+            Collider::Rectangle { width, height } => {
+                let half_size = Vec2::new(*width, *height) / 2.0;
+                (-half_size, half_size)
+            }
+            Collider::Circle { radius } => (Vec2::splat(-radius), Vec2::splat(*radius)),
+            Collider::ConvexPolygon { points } => points.iter().fold(
+                (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+                |(min, max), p| (min.min(*p), max.max(*p)),
+            ),
+            Collider::Capsule {
+                half_length,
+                radius,
+            } => {
+                let half_size = Vec2::new(half_length + radius, *radius);
+                (-half_size, half_size)
+            }
+        }
+    }
+
+    fn surface_point(&self, point: Vec2) -> SurfacePoint {
+        match self {
+            Collider::Rectangle { width, height } => {
+                let half_size = Vec2::new(*width, *height) / 2.0;
+                let closest = point.clamp(-half_size, half_size);
+                if closest != point {
+                    return SurfacePoint {
+                        point: closest,
+                        normal: (point - closest).normalize(),
+                        inside: false,
+                    };
+                }
+                // Push the point out through the closest side.
+                let overlap = half_size - point.abs();
+                let normal = if overlap.x < overlap.y {
+                    Vec2::new(sign(point.x), 0.0)
+                } else {
+                    Vec2::new(0.0, sign(point.y))
+                };
+                SurfacePoint {
+                    point: point + normal * overlap.min_element(),
+                    normal,
+                    inside: true,
+                }
+            }
+            Collider::Circle { radius } => round_surface_point(point, Vec2::ZERO, *radius),
+            Collider::ConvexPolygon { points } => {
+                let mut inside = true;
+                let mut closest = SurfacePoint {
+                    point,
+                    normal: Vec2::Y,
+                    inside: true,
+                };
+                let mut closest_distance = f32::INFINITY;
+                for (i, a) in points.iter().enumerate() {
+                    let b = points[(i + 1) % points.len()];
+                    let edge = b - *a;
+                    inside &= 0.0 <= edge.perp_dot(point - *a);
+
+                    let q = closest_point_on_segment(point, *a, b);
+                    let distance = point.distance_squared(q);
+                    if distance < closest_distance {
+                        closest_distance = distance;
+                        closest.point = q;
+                        closest.normal = Vec2::new(edge.y, -edge.x).normalize();
+                    }
+                }
+                closest.inside = inside;
+                if !inside {
+                    closest.normal = (point - closest.point)
+                        .try_normalize()
+                        .unwrap_or(closest.normal);
+                }
+                closest
+            }
+            Collider::Capsule {
+                half_length,
+                radius,
+            } => {
+                let center = closest_point_on_segment(
+                    point,
+                    Vec2::new(-half_length, 0.0),
+                    Vec2::new(*half_length, 0.0),
+                );
+                round_surface_point(point, center, *radius)
+            }
+        }
+    }
+}
+
+/// Surface point of a circle, also used for the rounded parts of capsules.
+fn round_surface_point(point: Vec2, center: Vec2, radius: f32) -> SurfacePoint {
+    let offset = point - center;
+    let distance = offset.length();
+    let normal = offset.try_normalize().unwrap_or(Vec2::Y);
+    SurfacePoint {
+        point: center + normal * radius,
+        normal,
+        inside: distance <= radius,
+    }
+}
+
+fn closest_point_on_segment(point: Vec2, a: Vec2, b: Vec2) -> Vec2 {
+    let ab = b - a;
+    let t = ((point - a).dot(ab) / ab.length_squared()).clamp(0.0, 1.0);
+    a + ab * t
+}
+
+/// Like `signum`, but never zero for points in the middle of a shape.
+fn sign(value: f32) -> f32 {
+    if value < 0.0 {
+        -1.0
+    } else {
+        1.0
+    }
+}
+
+/// Maps collider local space to the world XZ plane.
+pub fn collider_frame(transform: &Transform) -> Affine2 {
+    Affine2::from_cols(
+        (transform.rotation * Vec3::X).xz(),
+        (transform.rotation * Vec3::Z).xz(),
+        transform.translation.xz(),
+    )
+}
+
+/// World space bounding box of a collider.
+pub fn collider_aabb(collider: &Collider, transform: &Transform) -> (Vec2, Vec2) {
+    let frame = collider_frame(transform);
+    let (min, max) = collider.local_aabb();
+    [min, Vec2::new(min.x, max.y), max, Vec2::new(max.x, min.y)]
+        .into_iter()
+        .map(|corner| frame.transform_point2(corner))
+        .fold(
+            (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+            |(min, max), p| (min.min(p), max.max(p)),
+        )
 }
 
 #[derive(Component, Debug)]
 pub struct Dynamic;
 
+/// Marks a ball which came to rest. Sleeping balls are not integrated and
+/// do not generate collisions with static colliders or other sleeping balls.
+/// They are woken up by contacts with awake balls and by nearby merges.
+#[derive(Component, Debug)]
+pub struct Sleeping;
+
+/// Time a ball has spent below the sleep thresholds.
+#[derive(Component, Debug, Default)]
+pub struct SleepTimer {
+    pub time: f32,
+}
+
+/// Ball or platform translation and rotation at the start and at the end of
+/// the last physics step. Physics systems always see `current`, while the
+/// rendered `Transform` is interpolated between the two by the fixed step
+/// overstep.
+#[derive(Component, Debug)]
+pub struct InterpolatedTransform {
+    pub previous: Vec3,
+    pub current: Vec3,
+    pub previous_rotation: Quat,
+    pub current_rotation: Quat,
+}
+
+impl InterpolatedTransform {
+    pub fn new(translation: Vec3) -> Self {
+        Self {
+            previous: translation,
+            current: translation,
+            previous_rotation: Quat::IDENTITY,
+            current_rotation: Quat::IDENTITY,
+        }
+    }
+}
+
+/// Uniform grid over the XZ plane. Each item is stored in the single cell
+/// containing its center, so with a cell size of at least twice the largest
+/// radius every overlapping pair ends up in neighbouring cells.
+#[derive(Debug, Default)]
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<IVec2, Vec<usize>>,
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::default(),
+        }
+    }
+
+    /// Removes all items and sets a new cell size. Cells which were empty
+    /// since the previous clear are dropped, the rest keep their allocations.
+    pub fn clear(&mut self, cell_size: f32) {
+        self.cell_size = cell_size;
+        self.cells.retain(|_, items| {
+            let used = !items.is_empty();
+            items.clear();
+            used
+        });
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    /// Cells holding at least one item.
+    pub fn occupied_cells(&self) -> impl Iterator<Item = IVec2> + '_ {
+        self.cells
+            .iter()
+            .filter(|(_, items)| !items.is_empty())
+            .map(|(cell, _)| *cell)
+    }
+
+    pub fn cell(&self, position: Vec2) -> IVec2 {
+        (position / self.cell_size).floor().as_ivec2()
+    }
+
+    pub fn insert(&mut self, index: usize, position: Vec2) {
+        let cell = self.cell(position);
+        self.cells.entry(cell).or_default().push(index);
+    }
+
+    /// Appends every pair of items from the same or neighbouring cells, each
+    /// pair once with the lower index first. `positions` must be indexed
+    /// the same way as the inserted items.
+    pub fn pairs(&self, positions: &[Vec2], pairs: &mut Vec<(usize, usize)>) {
+        const FORWARD: [IVec2; 4] = [
+            IVec2::new(1, 0),
+            IVec2::new(-1, 1),
+            IVec2::new(0, 1),
+            IVec2::new(1, 1),
+        ];
+
+        for (a, position) in positions.iter().enumerate() {
+            let cell = self.cell(*position);
+            if let Some(items) = self.cells.get(&cell) {
+                pairs.extend(items.iter().filter(|b| a < **b).map(|b| (a, *b)));
+            }
+            for offset in FORWARD {
+                if let Some(items) = self.cells.get(&(cell + offset)) {
+                    pairs.extend(items.iter().map(|b| (a.min(*b), a.max(*b))));
+                }
+            }
+        }
+    }
+
+    /// Appends every item which can overlap the `min`..`max` area, assuming
+    /// item radii are not bigger than half of the cell size.
+    pub fn query_aabb(&self, min: Vec2, max: Vec2, items: &mut Vec<usize>) {
+        let margin = Vec2::splat(self.cell_size / 2.0);
+        let min = self.cell(min - margin);
+        let max = self.cell(max + margin);
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                if let Some(cell_items) = self.cells.get(&IVec2::new(x, y)) {
+                    items.extend_from_slice(cell_items);
+                }
+            }
+        }
+    }
+}
+
+/// Broad phase state shared by the collision detection systems. Rebuilt
+/// every physics step from the positions of all `Dynamic` balls.
+#[derive(Debug, Default, Resource)]
+pub struct BroadPhase {
+    pub entities: Vec<Entity>,
+    pub positions: Vec<Vec2>,
+    pub grid: SpatialGrid,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Contact {
+    pub point: Vec2,
+    /// Unit vector pointing from the second body towards the first one.
+    pub normal: Vec2,
+    pub depth: f32,
+}
+
+/// Physics state drawn by the debug overlay. Contacts are only recorded
+/// while it is enabled.
+#[derive(Debug, Default, Resource)]
+pub struct PhysicsDebug {
+    pub enabled: bool,
+    /// Contacts found by the last physics step.
+    pub contacts: Vec<Contact>,
+}
+
+/// Contact between a `Dynamic` ball in `entity1` and either another ball or
+/// a static collider in `entity2`. Each touching pair is reported once.
 #[derive(Debug, Event)]
 pub struct CollisionEvent {
     pub entity1: Entity,
     pub entity2: Entity,
     pub collision_point: Vec2,
+    pub normal: Vec2,
+    pub depth: f32,
 }
 
-fn ball_rect_collision_system(
-    mut collision_events: EventWriter<CollisionEvent>,
+/// Sent for every merge of two balls, which are gone by then. Only drives
+/// visual effects.
+#[derive(Debug, Clone, Copy, Event)]
+pub struct MergeEvent {
+    /// Type of the merged balls.
+    pub item_type: u8,
+    /// Transforms of the merged balls.
+    pub sources: [Transform; 2],
+    /// Point where the balls touched.
+    pub position: Vec3,
+    /// Type of the created ball, `None` when the balls vanished.
+    pub merged_type: Option<u8>,
+}
+
+fn record_debug_contacts(
+    mut debug: ResMut<PhysicsDebug>,
+    mut collision_events: EventReader<CollisionEvent>,
+) {
+    debug.contacts.clear();
+    if !debug.enabled {
+        collision_events.clear();
+        return;
+    }
+    debug
+        .contacts
+        .extend(collision_events.read().map(|event| Contact {
+            point: event.collision_point,
+            normal: event.normal,
+            depth: event.depth,
+        }));
+}
+
+fn update_broad_phase(
+    mut broad_phase: ResMut<BroadPhase>,
     balls: Query<(Entity, &Ball, &Transform), With<Dynamic>>,
-    rectangles: Query<(Entity, &Rectangle, &Transform)>,
 ) {
-    for (ball_entity, ball, ball_transform) in balls.iter() {
-        for (rect_entity, rect, rect_transform) in rectangles.iter() {
-            if let Some(collision_point) =
-                ball_rect_collision(ball, ball_transform, rect, rect_transform)
+    let broad_phase = broad_phase.as_mut();
+    broad_phase.entities.clear();
+    broad_phase.positions.clear();
+
+    let mut max_radius: f32 = 0.0;
+    for (entity, ball, transform) in balls.iter() {
+        broad_phase.entities.push(entity);
+        broad_phase.positions.push(transform.translation.xz());
+        max_radius = max_radius.max(ball.radius);
+    }
+
+    // Without balls there is no radius to size the cells from, keep the
+    // previous size.
+    if broad_phase.entities.is_empty() {
+        let cell_size = broad_phase.grid.cell_size();
+        broad_phase.grid.clear(cell_size);
+        return;
+    }
+    broad_phase.grid.clear(max_radius * 2.0);
+    for (i, position) in broad_phase.positions.iter().enumerate() {
+        broad_phase.grid.insert(i, *position);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn ball_collider_collision_system(
+    broad_phase: Res<BroadPhase>,
+    mut collision_events: EventWriter<CollisionEvent>,
+    balls: Query<(&Ball, &Transform), (With<Dynamic>, Without<Sleeping>)>,
+    colliders: Query<(Entity, &Collider, &Transform)>,
+    mut candidates: Local<Vec<usize>>,
+) {
+    // The grid is only sized by the balls in it.
+    if broad_phase.entities.is_empty() {
+        return;
+    }
+    for (collider_entity, collider, collider_transform) in colliders.iter() {
+        let (min, max) = collider_aabb(collider, collider_transform);
+
+        candidates.clear();
+        broad_phase.grid.query_aabb(min, max, &mut candidates);
+
+        for i in candidates.iter() {
+            let ball_entity = broad_phase.entities[*i];
+            let Ok((ball, ball_transform)) = balls.get(ball_entity) else {
+                continue;
+            };
+            if let Some(contact) =
+                ball_collider_collision(ball, ball_transform, collider, collider_transform)
             {
                 collision_events.send(CollisionEvent {
                     entity1: ball_entity,
-                    entity2: rect_entity,
-                    collision_point,
+                    entity2: collider_entity,
+                    collision_point: contact.point,
+                    normal: contact.normal,
+                    depth: contact.depth,
                 });
             }
         }
     }
 }
 
-fn ball_rect_collision(
+fn ball_collider_collision(
     ball: &Ball,
     ball_transform: &Transform,
-    rect: &Rectangle,
-    rect_transform: &Transform,
-) -> Option<Vec2> {
-    let mut px = ball_transform.translation.x;
-    let mut pz = ball_transform.translation.z;
-    px = px.max(rect_transform.translation.x - rect.width / 2.0);
-    px = px.min(rect_transform.translation.x + rect.width / 2.0);
-    pz = pz.max(rect_transform.translation.z - rect.height / 2.0);
-    pz = pz.min(rect_transform.translation.z + rect.height / 2.0);
-
-    if (ball_transform.translation.x - px).powi(2) + (ball_transform.translation.z - pz).powi(2)
-        < ball.radius.powi(2)
-    {
-        Some(Vec2::new(px, pz))
+    collider: &Collider,
+    collider_transform: &Transform,
+) -> Option<Contact> {
+    let frame = collider_frame(collider_transform);
+    let center = ball_transform.translation.xz();
+    let surface = collider.surface_point(frame.inverse().transform_point2(center));
+    let point = frame.transform_point2(surface.point);
+    let normal = frame.transform_vector2(surface.normal);
+    let distance = center.distance(point);
+
+    if surface.inside {
+        Some(Contact {
+            point,
+            normal,
+            depth: ball.radius + distance,
+        })
+    } else if distance < ball.radius {
+        Some(Contact {
+            point,
+            normal,
+            depth: ball.radius - distance,
+        })
     } else {
         None
     }
 }
 
+/// Sweeps a ball of `radius` from `start` by `motion` against the collider
+/// with conservative advancement and returns the time of impact as
+/// a fraction of the motion together with the contact normal. Balls with
+/// the center inside of the collider are left to the discrete collision
+/// detection.
+fn swept_ball_collider(
+    radius: f32,
+    start: Vec2,
+    motion: Vec2,
+    collider: &Collider,
+    collider_transform: &Transform,
+) -> Option<(f32, Vec2)> {
+    let length = motion.length();
+    if length == 0.0 {
+        return None;
+    }
+
+    let frame = collider_frame(collider_transform);
+    let inverse = frame.inverse();
+    let mut t = 0.0;
+    for _ in 0..SWEEP_STEPS {
+        let local = inverse.transform_point2(start + motion * t);
+        let surface = collider.surface_point(local);
+        if surface.inside {
+            return None;
+        }
+        let distance = local.distance(surface.point) - radius;
+        if distance <= SWEEP_TOLERANCE {
+            let normal = frame.transform_vector2(surface.normal);
+            return (motion.dot(normal) < 0.0).then_some((t, normal));
+        }
+        t += distance / length;
+        if 1.0 < t {
+            return None;
+        }
+    }
+    None
+}
+
+/// Hit of a ray or shape cast.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CastHit {
+    pub entity: Entity,
+    /// Distance travelled along the cast direction.
+    pub distance: f32,
+    /// Point of contact on the surface of the hit shape.
+    pub point: Vec2,
+    /// Outward normal of the hit shape at `point`.
+    pub normal: Vec2,
+}
+
+/// Ray and circle casts in the XZ plane against `Dynamic` balls and static
+/// colliders, for systems that need to look ahead of the simulation.
+#[derive(SystemParam)]
+pub struct SpatialQuery<'w, 's> {
+    balls: Query<'w, 's, (Entity, &'static Ball, &'static Transform), With<Dynamic>>,
+    colliders: Query<'w, 's, (Entity, &'static Collider, &'static Transform)>,
+}
+
+impl SpatialQuery<'_, '_> {
+    pub fn cast_ray(
+        &self,
+        origin: Vec2,
+        direction: Vec2,
+        max_distance: f32,
+        filter: impl Fn(Entity) -> bool,
+    ) -> Option<CastHit> {
+        self.cast_circle(origin, 0.0, direction, max_distance, filter)
+    }
+
+    /// Moves a circle of `radius` from `origin` along `direction` and returns
+    /// the first shape it touches within `max_distance`. Shapes already
+    /// overlapping the circle at the origin and entities rejected by `filter`
+    /// are ignored.
+    pub fn cast_circle(
+        &self,
+        origin: Vec2,
+        radius: f32,
+        direction: Vec2,
+        max_distance: f32,
+        filter: impl Fn(Entity) -> bool,
+    ) -> Option<CastHit> {
+        let direction = direction.try_normalize()?;
+        let ray = Ray2 {
+            origin,
+            direction,
+            max_distance,
+        };
+        let balls = self.balls.iter().filter_map(|(entity, ball, transform)| {
+            let center = transform.translation.xz();
+            let (distance, normal) = ray.cast_circle(center, ball.radius + radius)?;
+            Some(CastHit {
+                entity,
+                distance,
+                point: center + normal * ball.radius,
+                normal,
+            })
+        });
+        let colliders = self
+            .colliders
+            .iter()
+            .filter_map(|(entity, collider, transform)| {
+                let frame = collider_frame(transform);
+                let inverse = frame.inverse();
+                let local_ray = Ray2 {
+                    origin: inverse.transform_point2(origin),
+                    direction: inverse.transform_vector2(direction),
+                    max_distance,
+                };
+                let (distance, normal) = local_ray.cast_collider(collider, radius)?;
+                let normal = frame.transform_vector2(normal);
+                Some(CastHit {
+                    entity,
+                    distance,
+                    point: origin + direction * distance - normal * radius,
+                    normal,
+                })
+            });
+        balls
+            .chain(colliders)
+            .filter(|hit| filter(hit.entity))
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+}
+
+/// Ray with a unit direction, cast against shapes inflated by the radius of
+/// the cast circle.
+struct Ray2 {
+    origin: Vec2,
+    direction: Vec2,
+    max_distance: f32,
+}
+
+impl Ray2 {
+    /// Distance to a circle and the normal there, if the ray starts outside.
+    fn cast_circle(&self, center: Vec2, radius: f32) -> Option<(f32, Vec2)> {
+        let offset = self.origin - center;
+        let c = offset.length_squared() - radius * radius;
+        let b = offset.dot(self.direction);
+        if c <= 0.0 || b >= 0.0 {
+            return None;
+        }
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let distance = -b - discriminant.sqrt();
+        (distance <= self.max_distance)
+            .then(|| (distance, (offset + self.direction * distance) / radius))
+    }
+
+    /// Distance to the segment from `a` to `b` moved out by `radius` along
+    /// `normal`, hit from the outside.
+    fn cast_edge(&self, a: Vec2, b: Vec2, normal: Vec2, radius: f32) -> Option<(f32, Vec2)> {
+        let approach = self.direction.dot(normal);
+        if approach >= 0.0 {
+            return None;
+        }
+        let offset = a + normal * radius;
+        let distance = (offset - self.origin).dot(normal) / approach;
+        if distance < 0.0 || self.max_distance < distance {
+            return None;
+        }
+        let edge = b - a;
+        let t =
+            (self.origin + self.direction * distance - offset).dot(edge) / edge.length_squared();
+        (0.0..=1.0).contains(&t).then_some((distance, normal))
+    }
+
+    /// Casts a circle of `radius` against a collider in its local space.
+    fn cast_collider(&self, collider: &Collider, radius: f32) -> Option<(f32, Vec2)> {
+        let surface = collider.surface_point(self.origin);
+        if surface.inside || self.origin.distance(surface.point) < radius {
+            return None;
+        }
+        // Every collider is a convex polygon with rounded corners.
+        let (points, rounding) = match collider {
+            Collider::Rectangle { width, height } => {
+                let (w, h) = (width / 2.0, height / 2.0);
+                let corners = vec![
+                    Vec2::new(-w, -h),
+                    Vec2::new(w, -h),
+                    Vec2::new(w, h),
+                    Vec2::new(-w, h),
+                ];
+                (corners, 0.0)
+            }
+            Collider::Circle { radius } => (vec![Vec2::ZERO], *radius),
+            Collider::ConvexPolygon { points } => (points.clone(), 0.0),
+            Collider::Capsule {
+                half_length,
+                radius,
+            } => (
+                vec![Vec2::new(-half_length, 0.0), Vec2::new(*half_length, 0.0)],
+                *radius,
+            ),
+        };
+        let radius = radius + rounding;
+
+        let corners = points
+            .iter()
+            .filter(|_| radius > 0.0)
+            .filter_map(|point| self.cast_circle(*point, radius));
+        // Segments have both sides, going back and forth.
+        let edge_count = if points.len() == 2 { 2 } else { points.len() };
+        let edges = (0..edge_count).filter_map(|i| {
+            let a = points[i];
+            let b = points[(i + 1) % points.len()];
+            let normal = (b - a).perp().try_normalize()?;
+            self.cast_edge(a, b, -normal, radius)
+        });
+        corners.chain(edges).min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn ball_ball_collision_system(
-    balls: Query<(Entity, &Ball, &Transform), With<Dynamic>>,
+    broad_phase: Res<BroadPhase>,
+    top_tier_merge: Res<TopTierMerge>,
+    items_resources: Res<ItemsResources>,
+    balls: Query<(&Ball, &Transform, Has<Sleeping>, Option<&Combo>), With<Dynamic>>,
     mut score: ResMut<Score>,
     mut commands: Commands,
     mut collision_events: EventWriter<CollisionEvent>,
+    mut score_events: EventWriter<ScoreEvent>,
     mut spawn_item_events: EventWriter<SpawnItemEvent>,
+    mut merge_events: EventWriter<MergeEvent>,
+    mut pairs: Local<Vec<(usize, usize)>>,
+    mut candidates: Local<Vec<usize>>,
 ) {
+    pairs.clear();
+    broad_phase.grid.pairs(&broad_phase.positions, &mut pairs);
+
     let mut removed_entities = vec![];
-    for [(ball_1_entity, ball_1, ball_1_transform), (ball_2_entity, ball_2, ball_2_transform)] in
-        balls.iter_combinations()
-    {
+    let mut woken_entities = vec![];
+    let mut merges = vec![];
+    for (i, j) in pairs.iter() {
+        let ball_1_entity = broad_phase.entities[*i];
+        let ball_2_entity = broad_phase.entities[*j];
         if removed_entities.contains(&ball_1_entity) || removed_entities.contains(&ball_2_entity) {
             continue;
         }
+        let Ok([ball_1, ball_2]) = balls.get_many([ball_1_entity, ball_2_entity]) else {
+            continue;
+        };
+        let (ball_1, ball_1_transform, ball_1_sleeping, ball_1_combo) = ball_1;
+        let (ball_2, ball_2_transform, ball_2_sleeping, ball_2_combo) = ball_2;
 
-        if let Some(collision_point) =
+        if let Some(contact) =
             ball_ball_collision(ball_1, ball_1_transform, ball_2, ball_2_transform)
         {
-            if ball_1.ball_type == ball_2.ball_type {
-                spawn_item_events.send(SpawnItemEvent {
-                    item_type: (ball_1.ball_type + 1) % NUM_ITEMS,
-                    position: Vec3::new(collision_point.x, 0.0, collision_point.y),
+            let merge = (ball_1.ball_type == ball_2.ball_type)
+                .then(|| top_tier_merge.merge(ball_1.ball_type, &items_resources))
+                .flatten();
+            if let Some(merge) = merge {
+                let position = Vec3::new(contact.point.x, 0.0, contact.point.y);
+                let combo = merge_depth(ball_1_combo, ball_2_combo);
+                let (points, merged_type) = match merge {
+                    MergeOutcome::Item {
+                        item_type,
+                        score: points,
+                    } => {
+                        spawn_item_events.send(SpawnItemEvent {
+                            item_type,
+                            position,
+                            combo,
+                            ..default()
+                        });
+                        (points, Some(item_type))
+                    }
+                    MergeOutcome::Vanish { bonus } => (bonus, None),
+                };
+                merge_events.send(MergeEvent {
+                    item_type: ball_1.ball_type,
+                    sources: [*ball_1_transform, *ball_2_transform],
+                    position,
+                    merged_type,
+                });
+                let tier = merged_type.unwrap_or(ball_1.ball_type);
+                let points = points * combo;
+                score.score += points;
+                score_events.send(ScoreEvent {
+                    points,
+                    tier,
+                    combo,
+                    position,
                 });
                 removed_entities.extend_from_slice(&[ball_1_entity, ball_2_entity]);
-                score.score += 1;
-            } else {
+                merges.push((contact.point, ball_1.radius + ball_2.radius));
+            } else if !(ball_1_sleeping && ball_2_sleeping) {
+                if ball_1_sleeping {
+                    woken_entities.push(ball_1_entity);
+                }
+                if ball_2_sleeping {
+                    woken_entities.push(ball_2_entity);
+                }
                 collision_events.send(CollisionEvent {
                     entity1: ball_1_entity,
                     entity2: ball_2_entity,
-                    collision_point,
-                });
-                collision_events.send(CollisionEvent {
-                    entity1: ball_2_entity,
-                    entity2: ball_1_entity,
-                    collision_point,
+                    collision_point: contact.point,
+                    normal: contact.normal,
+                    depth: contact.depth,
                 });
             }
         }
     }
 
+    // Balls around a merge may have lost their support.
+    for (point, radius) in merges {
+        let reach = Vec2::splat(radius + broad_phase.grid.cell_size());
+        candidates.clear();
+        broad_phase
+            .grid
+            .query_aabb(point - reach, point + reach, &mut candidates);
+        for i in candidates.iter() {
+            let entity = broad_phase.entities[*i];
+            let Ok((ball, transform, true, _)) = balls.get(entity) else {
+                continue;
+            };
+            if transform.translation.xz().distance(point) < radius + ball.radius {
+                woken_entities.push(entity);
+            }
+        }
+    }
+
+    for e in woken_entities {
+        if !removed_entities.contains(&e) {
+            commands
+                .entity(e)
+                .remove::<Sleeping>()
+                .insert(SleepTimer::default());
+        }
+    }
+
     for e in removed_entities {
         commands.entity(e).despawn();
     }
@@ -173,90 +1048,379 @@ fn ball_ball_collision(
     ball_1_transform: &Transform,
     ball_2: &Ball,
     ball_2_transform: &Transform,
-) -> Option<Vec2> {
-    let v = ball_1_transform.translation - ball_2_transform.translation;
+) -> Option<Contact> {
+    let v = ball_1_transform.translation.xz() - ball_2_transform.translation.xz();
     let radius_sum = ball_1.radius + ball_2.radius;
     let length = v.length();
     if length < radius_sum {
-        let delta = (radius_sum - length) / 4.0;
-        let offset = v.normalize() * (ball_2.radius - delta);
-        let center = ball_2_transform.translation + offset;
-        Some(Vec2::new(center.x, center.z))
+        // Perfectly overlapping balls are separated vertically.
+        let normal = v.try_normalize().unwrap_or(Vec2::Y);
+        let depth = radius_sum - length;
+        let point = ball_2_transform.translation.xz() + normal * (ball_2.radius - depth / 2.0);
+        Some(Contact {
+            point,
+            normal,
+            depth,
+        })
     } else {
         None
     }
 }
 
-fn balls_update(time: Res<Time>, mut balls: Query<(&mut Transform, &mut Velocity), With<Ball>>) {
-    for (mut transform, mut velocity) in balls.iter_mut() {
-        velocity.velocity.z =
-            (velocity.velocity.z - GRAVITY * time.delta().as_secs_f32()).max(-MAX_SPEED);
-        transform.translation += velocity.velocity * time.delta().as_secs_f32();
+fn restore_physics_transforms(
+    mut balls: Query<(&mut Transform, &mut InterpolatedTransform), With<Ball>>,
+) {
+    for (mut transform, mut interpolated) in balls.iter_mut() {
+        transform.translation = interpolated.current;
+        transform.rotation = interpolated.current_rotation;
+        interpolated.previous = interpolated.current;
+        interpolated.previous_rotation = interpolated.current_rotation;
     }
 }
 
-fn balls_collision_resolution(
-    mut collision_events: EventReader<CollisionEvent>,
-    mut balls: Query<(Entity, &Ball, &mut Velocity, &mut Transform), With<Dynamic>>,
+/// Integrates ball motion. Moves are swept against static colliders, so a
+/// fast ball stops at the first wall on its way instead of passing through.
+fn balls_update(
+    time: Res<Time>,
+    mut balls: Query<
+        (&Ball, &mut Transform, &mut Velocity, &mut AngularVelocity),
+        Without<Sleeping>,
+    >,
+    colliders: Query<(&Collider, &Transform), Without<Ball>>,
 ) {
-    for event in collision_events.read() {
-        for (ball_entity, ball, mut ball_velocity, mut ball_transform) in balls.iter_mut() {
-            if ball_entity == event.entity1 {
-                let normal = (ball_transform.translation.xz() - event.collision_point).normalize();
-                let velocity = ball_velocity.velocity.xz();
-
-                let reflected = velocity - 2.0 * (velocity.dot(normal)) * normal;
-                let reflected = Vec3::new(reflected.x, 0.0, reflected.y);
-                ball_velocity.velocity = reflected * ball.bounciness;
-
-                let collision_point =
-                    Vec3::new(event.collision_point.x, 0.0, event.collision_point.y);
-                let normal = Vec3::new(normal.x, 0.0, normal.y).normalize();
-                ball_transform.translation = collision_point + normal * ball.radius;
-            }
+    let dt = time.delta().as_secs_f32();
+    for (ball, mut transform, mut velocity, mut angular_velocity) in balls.iter_mut() {
+        velocity.velocity.z = (velocity.velocity.z - GRAVITY * dt).max(-MAX_SPEED);
+
+        let mut position = transform.translation.xz();
+        let mut motion = velocity.velocity.xz() * dt;
+        for _ in 0..SWEEP_ITERATIONS {
+            let hit = colliders
+                .iter()
+                .filter_map(|(collider, collider_transform)| {
+                    swept_ball_collider(ball.radius, position, motion, collider, collider_transform)
+                })
+                .min_by(|a, b| a.0.total_cmp(&b.0));
+            // Shallow hits, including resting contacts, are left to the
+            // discrete collision detection.
+            let deep_hit = hit.filter(|(toi, normal)| {
+                -(motion * (1.0 - toi)).dot(*normal) > ball.radius * SWEEP_DEPTH
+            });
+            let Some((toi, normal)) = deep_hit else {
+                position += motion;
+                break;
+            };
+
+            // Stop at the impact and slide along the wall for the rest of the move.
+            let rest = motion * (1.0 - toi);
+            position += motion * toi;
+            motion = rest - normal * rest.dot(normal);
+
+            let normal_speed = velocity.velocity.xz().dot(normal);
+            let restitution = if normal_speed < -RESTING_SPEED {
+                ball.bounciness
+            } else {
+                0.0
+            };
+            let bounce = normal * normal_speed * (1.0 + restitution);
+            velocity.velocity -= Vec3::new(bounce.x, 0.0, bounce.y);
         }
+        transform.translation.x = position.x;
+        transform.translation.z = position.y;
+
+        angular_velocity.velocity *= (1.0 - ANGULAR_DAMPING * dt).max(0.0);
+        transform.rotate_y(-angular_velocity.velocity * dt);
     }
 }
 
-fn debug_physics_event(
-    mut collision_events: EventReader<CollisionEvent>,
+#[allow(clippy::type_complexity)]
+fn update_sleeping(
+    time: Res<Time>,
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut balls: Query<
+        (
+            Entity,
+            &Ball,
+            &mut Velocity,
+            &mut AngularVelocity,
+            &mut SleepTimer,
+        ),
+        (With<Dynamic>, Without<Sleeping>),
+    >,
+) {
+    for (entity, ball, mut velocity, mut angular_velocity, mut sleep_timer) in balls.iter_mut() {
+        if velocity.velocity.length() < SLEEP_SPEED
+            && angular_velocity.velocity.abs() * ball.radius < SLEEP_SPEED
+        {
+            sleep_timer.time += time.delta_seconds();
+        } else {
+            sleep_timer.time = 0.0;
+        }
+
+        if SLEEP_TIME <= sleep_timer.time {
+            velocity.velocity = Vec3::ZERO;
+            angular_velocity.velocity = 0.0;
+            commands.entity(entity).insert(Sleeping);
+        }
+    }
+}
+
+fn store_physics_transforms(
+    mut balls: Query<(&Transform, &mut InterpolatedTransform), With<Ball>>,
 ) {
+    for (transform, mut interpolated) in balls.iter_mut() {
+        interpolated.current = transform.translation;
+        interpolated.current_rotation = transform.rotation;
+    }
+}
+
+fn interpolate_transforms(
+    time: Res<Time<Fixed>>,
+    mut balls: Query<(&mut Transform, &InterpolatedTransform), With<Ball>>,
+) {
+    let alpha = time.overstep_fraction();
+    for (mut transform, interpolated) in balls.iter_mut() {
+        transform.translation = interpolated.previous.lerp(interpolated.current, alpha);
+        transform.rotation = interpolated
+            .previous_rotation
+            .slerp(interpolated.current_rotation, alpha);
+    }
+}
+
+/// Contact being solved in the current step.
+struct SolverContact {
+    ball: Entity,
+    /// Other ball of the contact, `None` for static colliders.
+    other: Option<Entity>,
+    /// Static collider of the contact.
+    collider: Entity,
+    normal: Vec2,
+    friction: f32,
+    /// Normal velocity the contact should end up with.
+    target_speed: f32,
+    /// Normal impulse accumulated over the velocity iterations.
+    impulse: f32,
+    /// Tangential impulse accumulated over the velocity iterations.
+    tangent_impulse: f32,
+}
+
+/// Velocity state of a ball while solving a single contact.
+struct SolverBody {
+    inv_mass: f32,
+    inv_inertia: f32,
+    /// Contact point relative to the ball center.
+    offset: Vec2,
+    velocity: Vec2,
+    angular_velocity: f32,
+}
+
+impl SolverBody {
+    fn new(
+        ball: &Ball,
+        velocity: &Velocity,
+        angular_velocity: &AngularVelocity,
+        offset: Vec2,
+    ) -> Self {
+        Self {
+            inv_mass: 1.0 / ball.mass(),
+            inv_inertia: 1.0 / ball.inertia(),
+            offset,
+            velocity: velocity.velocity.xz(),
+            angular_velocity: angular_velocity.velocity,
+        }
+    }
+
+    fn point_velocity(&self) -> Vec2 {
+        self.velocity + self.offset.perp() * self.angular_velocity
+    }
+
+    /// Inverse of the mass felt by an impulse along `direction`.
+    fn inv_effective_mass(&self, direction: Vec2) -> f32 {
+        self.inv_mass + self.inv_inertia * self.offset.perp_dot(direction).powi(2)
+    }
+
+    fn apply_impulse(&mut self, impulse: Vec2) {
+        self.velocity += impulse * self.inv_mass;
+        self.angular_velocity += self.offset.perp_dot(impulse) * self.inv_inertia;
+    }
+
+    fn write(&self, velocity: &mut Velocity, angular_velocity: &mut AngularVelocity) {
+        velocity.velocity = Vec3::new(self.velocity.x, velocity.velocity.y, self.velocity.y);
+        angular_velocity.velocity = self.angular_velocity;
+    }
+}
+
+/// Sequential impulse solver. Velocities are solved first with restitution
+/// taken from the balls `bounciness` and Coulomb friction spinning the balls,
+/// then the remaining penetration is removed over a few position iterations,
+/// distributing both by inverse mass.
+fn balls_collision_resolution(
+    mut collision_events: EventReader<CollisionEvent>,
+    mut balls: Query<(&Ball, &mut Velocity, &mut AngularVelocity, &mut Transform), With<Dynamic>>,
+    colliders: Query<(&Collider, &Transform), Without<Ball>>,
+    mut contacts: Local<Vec<SolverContact>>,
+) {
+    contacts.clear();
     for event in collision_events.read() {
-        debug!("collision event: {:?}", event);
-        commands.spawn(PbrBundle {
-            mesh: meshes.add(Cuboid::from_size(Vec3::ONE).mesh()),
-            material: materials.add(Color::RED),
-            transform: Transform::from_xyz(event.collision_point.x, 2.0, event.collision_point.y),
-            ..default()
+        let other = balls.contains(event.entity2).then_some(event.entity2);
+        let (ball, velocity, _, _) = match balls.get(event.entity1) {
+            Ok(b) => b,
+            Err(_) => continue,
+        };
+
+        let mut relative_velocity = velocity.velocity.xz();
+        let mut restitution = ball.bounciness;
+        let mut friction = ball.friction;
+        if let Some((other_ball, other_velocity, _, _)) = other.and_then(|e| balls.get(e).ok()) {
+            relative_velocity -= other_velocity.velocity.xz();
+            restitution = restitution.min(other_ball.bounciness);
+            friction = (friction * other_ball.friction).sqrt();
+        }
+        let normal_speed = relative_velocity.dot(event.normal);
+        let target_speed = if normal_speed < -RESTING_SPEED {
+            -restitution * normal_speed
+        } else {
+            0.0
+        };
+
+        contacts.push(SolverContact {
+            ball: event.entity1,
+            other,
+            collider: event.entity2,
+            normal: event.normal,
+            friction,
+            target_speed,
+            impulse: 0.0,
+            tangent_impulse: 0.0,
         });
     }
+
+    for _ in 0..SOLVER_VELOCITY_ITERATIONS {
+        for contact in contacts.iter_mut() {
+            match contact.other {
+                Some(other) => {
+                    let Ok([ball_1, ball_2]) = balls.get_many_mut([contact.ball, other]) else {
+                        continue;
+                    };
+                    let (ball_1, mut velocity_1, mut angular_1, _) = ball_1;
+                    let (ball_2, mut velocity_2, mut angular_2, _) = ball_2;
+                    let mut body_1 = SolverBody::new(
+                        ball_1,
+                        &velocity_1,
+                        &angular_1,
+                        -contact.normal * ball_1.radius,
+                    );
+                    let mut body_2 = SolverBody::new(
+                        ball_2,
+                        &velocity_2,
+                        &angular_2,
+                        contact.normal * ball_2.radius,
+                    );
+                    solve_contact(contact, &mut body_1, Some(&mut body_2));
+                    body_1.write(&mut velocity_1, &mut angular_1);
+                    body_2.write(&mut velocity_2, &mut angular_2);
+                }
+                None => {
+                    let Ok((ball, mut velocity, mut angular, _)) = balls.get_mut(contact.ball)
+                    else {
+                        continue;
+                    };
+                    let mut body =
+                        SolverBody::new(ball, &velocity, &angular, -contact.normal * ball.radius);
+                    solve_contact(contact, &mut body, None);
+                    body.write(&mut velocity, &mut angular);
+                }
+            }
+        }
+    }
+
+    for _ in 0..SOLVER_POSITION_ITERATIONS {
+        for contact in contacts.iter() {
+            match contact.other {
+                Some(other) => {
+                    let Ok([(ball_1, _, _, mut transform_1), (ball_2, _, _, mut transform_2)]) =
+                        balls.get_many_mut([contact.ball, other])
+                    else {
+                        continue;
+                    };
+                    let Some(current) =
+                        ball_ball_collision(ball_1, &transform_1, ball_2, &transform_2)
+                    else {
+                        continue;
+                    };
+                    let inv_mass_1 = 1.0 / ball_1.mass();
+                    let inv_mass_2 = 1.0 / ball_2.mass();
+                    let correction = position_correction(&current, inv_mass_1 + inv_mass_2);
+                    transform_1.translation += correction * inv_mass_1;
+                    transform_2.translation -= correction * inv_mass_2;
+                }
+                None => {
+                    let Ok((collider, collider_transform)) = colliders.get(contact.collider) else {
+                        continue;
+                    };
+                    let Ok((ball, _, _, mut transform)) = balls.get_mut(contact.ball) else {
+                        continue;
+                    };
+                    let Some(current) =
+                        ball_collider_collision(ball, &transform, collider, collider_transform)
+                    else {
+                        continue;
+                    };
+                    let inv_mass = 1.0 / ball.mass();
+                    transform.translation += position_correction(&current, inv_mass) * inv_mass;
+                }
+            }
+        }
+    }
 }
 
-fn debug_physics_rect(
-    rects: Query<(&Transform, &Rectangle)>,
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut run: Local<bool>,
+/// Applies one iteration of the normal and friction impulses. Accumulated
+/// impulses are clamped so contacts can only push bodies apart and friction
+/// never exceeds `friction` times the normal impulse.
+fn solve_contact(
+    contact: &mut SolverContact,
+    body_1: &mut SolverBody,
+    mut body_2: Option<&mut SolverBody>,
 ) {
-    if *run {
-        return;
+    let relative_velocity = |body_1: &SolverBody, body_2: &Option<&mut SolverBody>| {
+        body_1.point_velocity() - body_2.as_ref().map_or(Vec2::ZERO, |b| b.point_velocity())
+    };
+
+    let normal = contact.normal;
+    let normal_speed = relative_velocity(body_1, &body_2).dot(normal);
+    let inv_mass_sum = body_1.inv_effective_mass(normal)
+        + body_2
+            .as_ref()
+            .map_or(0.0, |b| b.inv_effective_mass(normal));
+    let lambda = (contact.target_speed - normal_speed) / inv_mass_sum;
+    let accumulated = (contact.impulse + lambda).max(0.0);
+    let impulse = normal * (accumulated - contact.impulse);
+    contact.impulse = accumulated;
+    body_1.apply_impulse(impulse);
+    if let Some(body_2) = body_2.as_mut() {
+        body_2.apply_impulse(-impulse);
     }
-    for (transform, rectangle) in rects.iter() {
-        println!("lol");
-        commands.spawn(PbrBundle {
-            mesh: meshes.add(Cuboid::new(
-                rectangle.width,
-                10.0,
-                rectangle.height,
-            ).mesh()),
-            material: materials.add(Color::YELLOW_GREEN),
-            transform: Transform::from_translation(transform.translation),
-            ..default()
-        });
+
+    let tangent = normal.perp();
+    let tangent_speed = relative_velocity(body_1, &body_2).dot(tangent);
+    let inv_mass_sum = body_1.inv_effective_mass(tangent)
+        + body_2
+            .as_ref()
+            .map_or(0.0, |b| b.inv_effective_mass(tangent));
+    let lambda = -tangent_speed / inv_mass_sum;
+    let max_friction = contact.friction * contact.impulse;
+    let accumulated = (contact.tangent_impulse + lambda).clamp(-max_friction, max_friction);
+    let impulse = tangent * (accumulated - contact.tangent_impulse);
+    contact.tangent_impulse = accumulated;
+    body_1.apply_impulse(impulse);
+    if let Some(body_2) = body_2.as_mut() {
+        body_2.apply_impulse(-impulse);
     }
-    *run = true;
+}
+
+/// Returns the positional "impulse" removing a part of the contact depth.
+fn position_correction(contact: &Contact, inv_mass_sum: f32) -> Vec3 {
+    let depth = (contact.depth - PENETRATION_SLOP).max(0.0) * POSITION_CORRECTION;
+    let correction = contact.normal * depth / inv_mass_sum;
+    Vec3::new(correction.x, 0.0, correction.y)
 }
@@ -1,40 +1,93 @@
 use bevy::prelude::*;
-use rand::Rng;
+use serde::{Deserialize, Serialize};
 
-use std::{f32::consts::PI, ops::Range};
+use std::{collections::VecDeque, f32::consts::PI};
 
-use crate::physics::{Ball, Dynamic, Velocity};
+use crate::{
+    items::ItemsResources,
+    physics::{
+        AngularVelocity, Ball, Collider, Dynamic, InterpolatedTransform, PhysicsSystems,
+        SleepTimer, SpatialQuery, Velocity,
+    },
+    randomizer::ItemRandomizer,
+    score::Combo,
+    simulation_running, GameState, RestartEvent,
+};
 
 pub struct PlatformPlugin;
 
 impl Plugin for PlatformPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, init);
-        app.add_systems(Update, spawn_controller);
-        app.add_systems(Update, spawn_items);
+        // Once the item tiers exist.
+        app.add_systems(PostStartup, (init_item_queue, find_walls));
+        app.add_systems(
+            Update,
+            (init_item_queue, reset_platform).run_if(on_event::<RestartEvent>()),
+        );
+        // The platform is driven once per physics step, so a recorded input
+        // sequence replays the same session.
+        app.configure_sets(
+            FixedUpdate,
+            (PlatformSystems::Input, PlatformSystems::Control)
+                .chain()
+                .before(PhysicsSystems::Movement),
+        );
+        app.add_systems(
+            FixedUpdate,
+            (
+                restore_platform_transform
+                    .run_if(simulation_running)
+                    .before(PlatformSystems::Input),
+                spawn_controller
+                    .run_if(in_state(GameState::Playing))
+                    .in_set(PlatformSystems::Control),
+                store_platform_transform
+                    .run_if(simulation_running)
+                    .after(PlatformSystems::Control),
+            ),
+        );
+        // `previous` is not reset while paused, the platform would jitter.
+        app.add_systems(
+            Update,
+            (
+                interpolate_platform_transform.run_if(simulation_running),
+                ghost_item_update,
+                update_drop_guide,
+            )
+                .chain()
+                .in_set(PlatformSystems::Interpolation),
+        );
+        // Merges are sent from the physics step, so items are spawned there
+        // as well to keep runs independent of the frame rate.
+        app.add_systems(
+            FixedUpdate,
+            spawn_items.after(PhysicsSystems::CollisionResolution),
+        );
         app.add_event::<SpawnItemEvent>();
+        app.init_resource::<PlatformInput>();
         app.init_resource::<SpawnItemTimer>();
+        app.init_resource::<ItemQueue>();
+        app.init_resource::<ItemRandomizer>();
+        app.init_resource::<PlatformBounds>();
+        app.init_resource::<DropGuide>();
     }
 }
 
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PlatformSystems {
+    /// Fills `PlatformInput` for the current physics step, from the
+    /// `ControlsPlugin` bindings or a replay.
+    Input,
+    Control,
+    /// Moves the rendered platform between its last two physics steps in
+    /// `Update`, followed by its ghost and the drop guide.
+    Interpolation,
+}
+
 pub const SPAWN_OFFSET: Vec3 = Vec3::new(0.0, 0.0, -1.0);
-pub const SPAWN_RANGE: Range<u8> = 0..2;
-pub const NUM_ITEMS: u8 = 5;
-pub const ITEM_1_RADIUS: f32 = 5.0;
-pub const ITEM_1_BOUNCINESS: f32 = 0.5;
-pub const ITEM_1_COLOR: Color = Color::GRAY;
-pub const ITEM_2_RADIUS: f32 = 8.0;
-pub const ITEM_2_BOUNCINESS: f32 = 0.4;
-pub const ITEM_2_COLOR: Color = Color::YELLOW_GREEN;
-pub const ITEM_3_RADIUS: f32 = 13.0;
-pub const ITEM_3_BOUNCINESS: f32 = 0.3;
-pub const ITEM_3_COLOR: Color = Color::GREEN;
-pub const ITEM_4_RADIUS: f32 = 16.0;
-pub const ITEM_4_BOUNCINESS: f32 = 0.2;
-pub const ITEM_4_COLOR: Color = Color::GOLD;
-pub const ITEM_5_RADIUS: f32 = 19.0;
-pub const ITEM_5_BOUNCINESS: f32 = 0.1;
-pub const ITEM_5_COLOR: Color = Color::ORANGE_RED;
+/// Number of items known in advance after `Platform::next_item`.
+pub const QUEUE_LENGTH: usize = 3;
 
 #[derive(Component)]
 pub struct Platform {
@@ -42,148 +95,235 @@ pub struct Platform {
     pub next_item: u8,
 }
 
-#[derive(Event)]
+/// Player input for the current physics step.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PlatformInput {
+    /// Movement along X, from -1 to 1.
+    pub movement: f32,
+    pub drop: bool,
+}
+
+/// Items dropped after `Platform::next_item`, in order.
+#[derive(Resource, Default)]
+pub struct ItemQueue {
+    pub items: VecDeque<u8>,
+}
+
+/// Where `Platform::next_item` would first touch something if dropped now.
+#[derive(Resource, Debug)]
+pub struct DropGuide {
+    pub enabled: bool,
+    /// Center of the item at that point, `None` while disabled or when
+    /// nothing is below the platform.
+    pub landing: Option<Vec3>,
+}
+
+impl Default for DropGuide {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            landing: None,
+        }
+    }
+}
+
+/// Translucent copy of `Platform::next_item` hanging where it will drop.
+#[derive(Component)]
+pub struct GhostItem {
+    pub item_type: u8,
+}
+
+/// Area the platform moves in, set by the level.
+#[derive(Debug, Clone, Copy, Resource)]
+pub struct PlatformBounds {
+    pub height: f32,
+    pub min_x: f32,
+    pub max_x: f32,
+    /// Inner sides of the walls at the height items are dropped from, found
+    /// from the scene colliders once they are spawned. Items are dropped
+    /// between them.
+    pub walls: Option<(f32, f32)>,
+}
+
+impl PlatformBounds {
+    /// Closest platform position to `x` that drops an item of `radius`
+    /// within the bounds.
+    pub fn clamp(&self, x: f32, radius: f32) -> f32 {
+        let (mut min_x, mut max_x) = (self.min_x, self.max_x);
+        if let Some((left, right)) = self.walls {
+            min_x = min_x.max(left + radius);
+            max_x = max_x.min(right - radius);
+        }
+        if min_x <= max_x {
+            x.clamp(min_x, max_x)
+        } else {
+            (min_x + max_x) / 2.0
+        }
+    }
+}
+
+impl Default for PlatformBounds {
+    fn default() -> Self {
+        Self {
+            height: 100.0,
+            min_x: 0.0,
+            max_x: 100.0,
+            walls: None,
+        }
+    }
+}
+
+#[derive(Event, Default)]
 pub struct SpawnItemEvent {
     pub item_type: u8,
     pub position: Vec3,
+    /// Depth of the merge chain that created the item, 0 for dropped items.
+    pub combo: u32,
+    /// Initial motion, for items restored from a save.
+    pub rotation: Quat,
+    pub velocity: Vec3,
+    pub angular_velocity: f32,
 }
 
 #[derive(Resource)]
 pub struct SpawnItemTimer {
     pub timer: Timer,
+    /// Drop started since the last one, done once the timer allows it even
+    /// if the input was released by then.
+    pub queued: bool,
+    /// `PlatformInput::drop` of the previous step.
+    pub held: bool,
 }
 
 impl Default for SpawnItemTimer {
     fn default() -> Self {
         Self {
             timer: Timer::from_seconds(1.0, TimerMode::Repeating),
+            queued: false,
+            held: false,
         }
     }
 }
 
-pub struct ItemResource {
-    pub mesh: Handle<Mesh>,
-    pub material: Handle<StandardMaterial>,
-    pub radius: f32,
-    pub bounciness: f32,
-}
-
-#[derive(Resource)]
-pub struct ItemsResources {
-    pub resources: [ItemResource; NUM_ITEMS as usize],
-}
-
+/// Rendering assets are optional so the game also runs headless, in which
+/// case the platform gets default handles.
 fn init(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    bounds: Res<PlatformBounds>,
+    meshes: Option<ResMut<Assets<Mesh>>>,
+    materials: Option<ResMut<Assets<StandardMaterial>>>,
 ) {
-    let item_1_mesh = meshes.add(
-        Sphere {
-            radius: ITEM_1_RADIUS,
-        }
-        .mesh()
-        .build(),
-    );
-    let item_1_material = materials.add(ITEM_1_COLOR);
-    let item_2_mesh = meshes.add(
-        Sphere {
-            radius: ITEM_2_RADIUS,
-        }
-        .mesh()
-        .build(),
-    );
-    let item_2_material = materials.add(ITEM_2_COLOR);
-    let item_3_mesh = meshes.add(
-        Sphere {
-            radius: ITEM_3_RADIUS,
-            ..default()
-        }
-        .mesh()
-        .build(),
-    );
-    let item_3_material = materials.add(ITEM_3_COLOR);
-    let item_4_mesh = meshes.add(
-        Sphere {
-            radius: ITEM_4_RADIUS,
-            ..default()
-        }
-        .mesh()
-        .build(),
-    );
-    let item_4_material = materials.add(ITEM_4_COLOR);
-    let item_5_mesh = meshes.add(
-        Sphere {
-            radius: ITEM_5_RADIUS,
-            ..default()
-        }
-        .mesh()
-        .build(),
-    );
-    let item_5_material = materials.add(ITEM_5_COLOR);
-
-    commands.insert_resource(ItemsResources {
-        resources: [
-            ItemResource {
-                mesh: item_1_mesh,
-                material: item_1_material,
-                radius: ITEM_1_RADIUS,
-                bounciness: ITEM_1_BOUNCINESS,
-            },
-            ItemResource {
-                mesh: item_2_mesh,
-                material: item_2_material,
-                radius: ITEM_2_RADIUS,
-                bounciness: ITEM_2_BOUNCINESS,
-            },
-            ItemResource {
-                mesh: item_3_mesh,
-                material: item_3_material,
-                radius: ITEM_3_RADIUS,
-                bounciness: ITEM_3_BOUNCINESS,
-            },
-            ItemResource {
-                mesh: item_4_mesh,
-                material: item_4_material,
-                radius: ITEM_4_RADIUS,
-                bounciness: ITEM_4_BOUNCINESS,
-            },
-            ItemResource {
-                mesh: item_5_mesh,
-                material: item_5_material,
-                radius: ITEM_5_RADIUS,
-                bounciness: ITEM_5_BOUNCINESS,
-            },
-        ],
-    });
-
-    let mesh = meshes.add(
-        Capsule3d {
-            radius: 2.0,
-            half_length: 5.0,
-        }
-        .mesh(),
-    );
-    let material = materials.add(Color::GOLD);
+    let mesh = meshes
+        .map(|mut meshes| {
+            meshes.add(
+                Capsule3d {
+                    radius: 2.0,
+                    half_length: 5.0,
+                }
+                .mesh(),
+            )
+        })
+        .unwrap_or_default();
+    let (material, ghost_material) = materials
+        .map(|mut materials| {
+            (
+                materials.add(Color::GOLD),
+                materials.add(StandardMaterial {
+                    base_color: Color::rgba(1.0, 1.0, 1.0, 0.3),
+                    alpha_mode: AlphaMode::Blend,
+                    ..default()
+                }),
+            )
+        })
+        .unwrap_or_default();
 
     let mut transform = Transform::from_rotation(Quat::from_rotation_z(PI / 2.0));
-    transform.translation = Vec3::new(50.0, 0.0, 100.0);
+    transform.translation = Vec3::new((bounds.min_x + bounds.max_x) / 2.0, 0.0, bounds.height);
     commands
         .spawn(PbrBundle {
-            mesh: mesh.clone(),
-            material: material.clone(),
+            mesh,
+            material,
             transform,
             ..default()
         })
         .insert(Platform {
             speed: 100.0,
             next_item: 0,
-        });
+        })
+        .insert(InterpolatedTransform::new(transform.translation));
+    commands
+        .spawn(PbrBundle {
+            material: ghost_material,
+            transform: Transform::from_translation(transform.translation + SPAWN_OFFSET),
+            ..default()
+        })
+        .insert(GhostItem { item_type: 0 });
+}
+
+/// Casts rays from the platform towards both sides to find the walls the
+/// dropped items have to stay between.
+fn find_walls(
+    spatial_query: SpatialQuery,
+    colliders: Query<(), With<Collider>>,
+    platform: Query<&Transform, With<Platform>>,
+    mut bounds: ResMut<PlatformBounds>,
+) {
+    let Ok(transform) = platform.get_single() else {
+        return;
+    };
+    let start = (transform.translation + SPAWN_OFFSET).xz();
+    let wall = |direction: Vec2| {
+        spatial_query
+            .cast_ray(start, direction, f32::INFINITY, |entity| {
+                colliders.contains(entity)
+            })
+            .map(|hit| hit.point.x)
+    };
+    bounds.walls = wall(Vec2::NEG_X).zip(wall(Vec2::X));
+    if bounds.walls.is_none() {
+        warn!("no walls on both sides of the platform");
+    }
+}
+
+/// Moves the platform back to the center, ready to drop.
+fn reset_platform(
+    bounds: Res<PlatformBounds>,
+    mut spawn_item_timer: ResMut<SpawnItemTimer>,
+    mut platform: Query<(&mut Transform, &mut InterpolatedTransform), With<Platform>>,
+) {
+    for (mut transform, mut interpolated) in platform.iter_mut() {
+        transform.translation.x = (bounds.min_x + bounds.max_x) / 2.0;
+        interpolated.previous = transform.translation;
+        interpolated.current = transform.translation;
+    }
+    spawn_item_timer.timer.reset();
+    spawn_item_timer.queued = false;
 }
 
+fn init_item_queue(
+    items_resources: Res<ItemsResources>,
+    mut randomizer: ResMut<ItemRandomizer>,
+    mut item_queue: ResMut<ItemQueue>,
+    mut platform: Query<&mut Platform>,
+) {
+    for mut platform in platform.iter_mut() {
+        platform.next_item = randomizer.next_item(&items_resources);
+    }
+    item_queue.items.clear();
+    for _ in 0..QUEUE_LENGTH {
+        let item_type = randomizer.next_item(&items_resources);
+        item_queue.items.push_back(item_type);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn spawn_controller(
     time: Res<Time>,
-    keys: Res<ButtonInput<KeyCode>>,
+    bounds: Res<PlatformBounds>,
+    items_resources: Res<ItemsResources>,
+    input: Res<PlatformInput>,
+    mut randomizer: ResMut<ItemRandomizer>,
+    mut item_queue: ResMut<ItemQueue>,
     mut spawn_item_timer: ResMut<SpawnItemTimer>,
     mut spawn_item_events: EventWriter<SpawnItemEvent>,
     mut platform: Query<(&mut Transform, &mut Platform)>,
@@ -193,51 +333,146 @@ fn spawn_controller(
         Err(_) => return,
     };
 
-    let mut dir = None;
-    if keys.pressed(KeyCode::KeyA) {
-        dir = Some(Vec3::NEG_X);
-    }
-    if keys.pressed(KeyCode::KeyD) {
-        dir = Some(Vec3::X);
-    }
-
     spawn_item_timer.timer.tick(time.delta());
-    if keys.pressed(KeyCode::Space) && spawn_item_timer.timer.finished() {
+    if input.drop && !spawn_item_timer.held {
+        spawn_item_timer.queued = true;
+    }
+    spawn_item_timer.held = input.drop;
+    if (input.drop || spawn_item_timer.queued) && spawn_item_timer.timer.finished() {
+        spawn_item_timer.queued = false;
         spawn_item_events.send(SpawnItemEvent {
             item_type: platform.next_item,
             position: platform_transform.translation + SPAWN_OFFSET,
+            ..default()
         });
-        platform.next_item = rand::thread_rng().gen_range(SPAWN_RANGE);
+        item_queue
+            .items
+            .push_back(randomizer.next_item(&items_resources));
+        platform.next_item = item_queue.items.pop_front().unwrap_or_default();
+    }
+
+    let movement = input.movement.clamp(-1.0, 1.0);
+    let translation = &mut platform_transform.translation;
+    translation.x += movement * time.delta().as_secs_f32() * platform.speed;
+    // The next item may be bigger than the one just dropped.
+    let radius = items_resources
+        .get(platform.next_item)
+        .map_or(0.0, |item| item.radius);
+    translation.x = bounds.clamp(translation.x, radius);
+}
+
+/// Puts the platform back where the last physics step left it, for the
+/// controls and the spawn controller.
+fn restore_platform_transform(
+    mut platform: Query<(&mut Transform, &mut InterpolatedTransform), With<Platform>>,
+) {
+    for (mut transform, mut interpolated) in platform.iter_mut() {
+        transform.translation = interpolated.current;
+        interpolated.previous = interpolated.current;
     }
+}
 
-    if let Some(dir) = dir {
-        platform_transform.translation += dir * time.delta().as_secs_f32() * platform.speed;
+fn store_platform_transform(
+    mut platform: Query<(&Transform, &mut InterpolatedTransform), With<Platform>>,
+) {
+    for (transform, mut interpolated) in platform.iter_mut() {
+        interpolated.current = transform.translation;
     }
 }
 
+/// Only the translation, the platform does not rotate.
+fn interpolate_platform_transform(
+    time: Res<Time<Fixed>>,
+    mut platform: Query<(&mut Transform, &InterpolatedTransform), With<Platform>>,
+) {
+    let alpha = time.overstep_fraction();
+    for (mut transform, interpolated) in platform.iter_mut() {
+        transform.translation = interpolated.previous.lerp(interpolated.current, alpha);
+    }
+}
+
+/// Keeps the ghost under the platform, looking like the next item.
+fn ghost_item_update(
+    items_resources: Res<ItemsResources>,
+    platform: Query<(&Transform, &Platform)>,
+    mut ghost: Query<(&mut Transform, &mut Handle<Mesh>, &mut GhostItem), Without<Platform>>,
+) {
+    let (Ok((platform_transform, platform)), Ok((mut transform, mut mesh, mut ghost))) =
+        (platform.get_single(), ghost.get_single_mut())
+    else {
+        return;
+    };
+
+    transform.translation = platform_transform.translation + SPAWN_OFFSET;
+    if ghost.item_type != platform.next_item || items_resources.is_changed() {
+        ghost.item_type = platform.next_item;
+        if let Some(resources) = items_resources.get(ghost.item_type) {
+            *mesh = resources.mesh.clone();
+        }
+    }
+}
+
+fn update_drop_guide(
+    items_resources: Res<ItemsResources>,
+    spatial_query: SpatialQuery,
+    platform: Query<(&Transform, &Platform)>,
+    mut drop_guide: ResMut<DropGuide>,
+) {
+    drop_guide.landing = None;
+    if !drop_guide.enabled {
+        return;
+    }
+    let Ok((transform, platform)) = platform.get_single() else {
+        return;
+    };
+    let Some(item) = items_resources.get(platform.next_item) else {
+        return;
+    };
+
+    let start = transform.translation + SPAWN_OFFSET;
+    let hit =
+        spatial_query.cast_circle(start.xz(), item.radius, Vec2::NEG_Y, f32::INFINITY, |_| {
+            true
+        });
+    drop_guide.landing = hit.map(|hit| start - Vec3::Z * hit.distance);
+}
+
 fn spawn_items(
     items_resources: Res<ItemsResources>,
     mut spawn_item_events: EventReader<SpawnItemEvent>,
     mut commands: Commands,
 ) {
     for event in spawn_item_events.read() {
-        let resources = &items_resources.resources[event.item_type as usize];
-
-        commands
-            .spawn(PbrBundle {
-                mesh: resources.mesh.clone(),
-                material: resources.material.clone(),
-                transform: Transform::from_translation(event.position),
-                ..default()
-            })
-            .insert(Ball {
-                radius: resources.radius,
-                bounciness: resources.bounciness,
-                ball_type: event.item_type,
-            })
-            .insert(Dynamic)
-            .insert(Velocity {
-                velocity: Vec3::default(),
-            });
+        let Some(resources) = items_resources.get(event.item_type) else {
+            warn!("cannot spawn an item of unknown type {}", event.item_type);
+            continue;
+        };
+
+        let transform = Transform::from_translation(event.position).with_rotation(event.rotation);
+        let mut interpolated_transform = InterpolatedTransform::new(event.position);
+        interpolated_transform.previous_rotation = event.rotation;
+        interpolated_transform.current_rotation = event.rotation;
+        let mut item = commands.spawn(PbrBundle {
+            mesh: resources.mesh.clone(),
+            material: resources.material.clone(),
+            transform,
+            ..default()
+        });
+        item.insert(Ball {
+            radius: resources.radius,
+            bounciness: resources.bounciness,
+            friction: resources.friction,
+            ball_type: event.item_type,
+        })
+        .insert(Dynamic)
+        .insert(interpolated_transform)
+        .insert(Velocity {
+            velocity: event.velocity,
+        })
+        .insert(AngularVelocity {
+            velocity: event.angular_velocity,
+        })
+        .insert(SleepTimer::default())
+        .insert(Combo::new(event.combo));
     }
 }
@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::items::ItemsResources;
+
+/// Number of times each droppable tier is put in a bag.
+pub const BAG_COPIES: usize = 2;
+
+/// How the platform picks the items it drops, among the tiers with a positive
+/// spawn weight.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Randomizer {
+    /// Every droppable tier is equally likely.
+    Uniform,
+    /// Tiers are picked according to their spawn weights.
+    #[default]
+    Weighted,
+    /// Droppable tiers are shuffled in bags holding `BAG_COPIES` of each, so
+    /// no tier goes missing for long.
+    Bag,
+}
+
+/// Source of the item sequence. The same seed and randomizer always give the
+/// same items. Insert one before the app starts to override the default,
+/// which is weighted with a random seed.
+#[derive(Resource)]
+pub struct ItemRandomizer {
+    pub randomizer: Randomizer,
+    pub seed: u64,
+    rng: StdRng,
+    bag: Vec<u8>,
+    /// Number of items picked since the start of the sequence.
+    draws: u64,
+}
+
+impl ItemRandomizer {
+    pub fn new(randomizer: Randomizer, seed: u64) -> Self {
+        Self {
+            randomizer,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            bag: vec![],
+            draws: 0,
+        }
+    }
+
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+
+    /// Picks and discards items until `draws` items were picked since the
+    /// start of the sequence, which restores a saved randomizer as long as
+    /// the item tiers did not change.
+    pub fn fast_forward(&mut self, draws: u64, items: &ItemsResources) {
+        while self.draws < draws {
+            self.next_item(items);
+        }
+    }
+
+    /// Starts the sequence over.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.randomizer, self.seed);
+    }
+
+    pub fn next_item(&mut self, items: &ItemsResources) -> u8 {
+        self.draws += 1;
+        match self.randomizer {
+            Randomizer::Uniform => *items
+                .droppable_tiers()
+                .collect::<Vec<_>>()
+                .choose(&mut self.rng)
+                .expect("item tiers are validated"),
+            Randomizer::Weighted => items.random_tier(&mut self.rng),
+            Randomizer::Bag => loop {
+                // Tiers may have been reloaded since the bag was filled.
+                match self.bag.pop() {
+                    Some(item_type) if items.tiers.get(item_type as usize).is_some() => {
+                        break item_type;
+                    }
+                    Some(_) => {}
+                    None => {
+                        for item_type in items.droppable_tiers() {
+                            self.bag.extend([item_type; BAG_COPIES]);
+                        }
+                        self.bag.shuffle(&mut self.rng);
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl Default for ItemRandomizer {
+    fn default() -> Self {
+        Self::new(Randomizer::default(), rand::random())
+    }
+}
@@ -0,0 +1,288 @@
+use bevy::{app::AppExit, prelude::*};
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    physics::TopTierMerge,
+    platform::{PlatformInput, PlatformSystems},
+    randomizer::{ItemRandomizer, Randomizer},
+    scene::Level,
+    simulation_running, RestartEvent,
+};
+
+/// Version of the replay format. Replays of other versions are rejected.
+pub const REPLAY_VERSION: u32 = 2;
+
+/// Records the platform input of every physics step, or plays it back in
+/// place of the player. Steps skipped while paused are not recorded.
+/// Replays cover a single game: a restart ends the recording or the
+/// playback. `Replay::check` tells whether a replay fits the level and the
+/// physics settings it is played with.
+pub struct ReplayPlugin {
+    pub mode: ReplayMode,
+}
+
+pub enum ReplayMode {
+    /// Records the session and saves it to the path when the app exits.
+    Record(PathBuf),
+    Play(Replay),
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        match &self.mode {
+            ReplayMode::Record(path) => {
+                app.insert_resource(ReplayRecorder {
+                    path: path.clone(),
+                    replay: Replay::new(0, Randomizer::default(), 0.0),
+                    stopped: false,
+                });
+                app.add_systems(Startup, start_recording);
+                app.add_systems(
+                    FixedUpdate,
+                    record_input
+                        .after(PlatformSystems::Input)
+                        .before(PlatformSystems::Control)
+                        .run_if(simulation_running),
+                );
+                app.add_systems(Update, stop_recording.run_if(on_event::<RestartEvent>()));
+                app.add_systems(Last, save_replay_on_exit);
+            }
+            ReplayMode::Play(replay) => {
+                app.insert_resource(ItemRandomizer::new(replay.randomizer, replay.seed));
+                app.insert_resource(ReplayPlayer::new(replay.clone()));
+                app.add_systems(
+                    FixedUpdate,
+                    play_input
+                        .after(PlatformSystems::Input)
+                        .before(PlatformSystems::Control)
+                        .run_if(simulation_running),
+                );
+                app.add_systems(Update, stop_playing.run_if(on_event::<RestartEvent>()));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub seed: u64,
+    pub randomizer: Randomizer,
+    /// Physics steps per second of the recorded session.
+    pub tick_rate: f64,
+    pub top_tier_merge: TopTierMerge,
+    /// Name of the level played.
+    pub level: String,
+    /// `Level::fingerprint` of the level played.
+    pub level_fingerprint: u64,
+    /// Input of every physics step, with runs of equal inputs merged into
+    /// `(steps, input)` pairs.
+    pub inputs: Vec<(u32, PlatformInput)>,
+}
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("could not access replay: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse replay: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("could not write replay: {0}")]
+    Serialize(#[from] ron::Error),
+    #[error("invalid replay: {0}")]
+    Invalid(String),
+    #[error("replay does not match the game: {0}")]
+    Mismatch(String),
+}
+
+/// Leading field of every replay version.
+#[derive(Deserialize)]
+struct ReplayHeader {
+    version: u32,
+}
+
+impl Replay {
+    pub fn new(seed: u64, randomizer: Randomizer, tick_rate: f64) -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            seed,
+            randomizer,
+            tick_rate,
+            top_tier_merge: TopTierMerge::default(),
+            level: String::new(),
+            level_fingerprint: 0,
+            inputs: vec![],
+        }
+    }
+
+    /// Fails if the replay was recorded on another level or with another
+    /// `TopTierMerge`, which would play it back out of sync.
+    pub fn check(&self, level: &Level, top_tier_merge: TopTierMerge) -> Result<(), ReplayError> {
+        if self.level_fingerprint != level.fingerprint() {
+            return Err(ReplayError::Mismatch(format!(
+                "recorded on level {:?}, not on {:?}",
+                self.level, level.name
+            )));
+        }
+        if self.top_tier_merge != top_tier_merge {
+            return Err(ReplayError::Mismatch(format!(
+                "recorded with {:?}, not with {top_tier_merge:?}",
+                self.top_tier_merge
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn from_ron(ron: &str) -> Result<Self, ReplayError> {
+        let header: ReplayHeader = ron::de::from_str(ron)?;
+        if header.version != REPLAY_VERSION {
+            return Err(ReplayError::Invalid(format!(
+                "version {} is not supported, expected {REPLAY_VERSION}",
+                header.version
+            )));
+        }
+        let replay: Self = ron::de::from_str(ron)?;
+        if !replay.tick_rate.is_finite() || replay.tick_rate <= 0.0 {
+            return Err(ReplayError::Invalid(format!(
+                "tick rate of {}",
+                replay.tick_rate
+            )));
+        }
+        Ok(replay)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        Self::from_ron(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn to_ron(&self) -> Result<String, ReplayError> {
+        // Keeps every input on its own line.
+        let config = PrettyConfig::default().depth_limit(2);
+        Ok(ron::ser::to_string_pretty(self, config)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ReplayError> {
+        std::fs::write(path, self.to_ron()?)?;
+        Ok(())
+    }
+
+    /// Appends the input of the next physics step.
+    pub fn push(&mut self, input: PlatformInput) {
+        match self.inputs.last_mut() {
+            Some((steps, last)) if *last == input => *steps += 1,
+            _ => self.inputs.push((1, input)),
+        }
+    }
+
+    /// Number of recorded physics steps.
+    pub fn len(&self) -> u32 {
+        self.inputs.iter().map(|(steps, _)| steps).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+}
+
+/// Session being recorded.
+#[derive(Resource)]
+pub struct ReplayRecorder {
+    pub path: PathBuf,
+    pub replay: Replay,
+    /// Set once the game restarted, the steps after it are not recorded.
+    pub stopped: bool,
+}
+
+impl ReplayRecorder {
+    pub fn save(&self) -> Result<(), ReplayError> {
+        self.replay.save(&self.path)
+    }
+}
+
+/// Replay being played back.
+#[derive(Resource)]
+pub struct ReplayPlayer {
+    pub replay: Replay,
+    /// Index of the current run of inputs and steps played from it.
+    cursor: (usize, u32),
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            replay,
+            cursor: (0, 0),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.cursor.0 >= self.replay.inputs.len()
+    }
+
+    fn next_input(&mut self) -> Option<PlatformInput> {
+        let (index, played) = &mut self.cursor;
+        let (steps, input) = *self.replay.inputs.get(*index)?;
+        *played += 1;
+        if *played >= steps {
+            *index += 1;
+            *played = 0;
+        }
+        Some(input)
+    }
+}
+
+/// Takes the settings of the session once the randomizer is in place.
+fn start_recording(
+    randomizer: Res<ItemRandomizer>,
+    time: Res<Time<Fixed>>,
+    level: Res<Level>,
+    top_tier_merge: Res<TopTierMerge>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    let mut replay = Replay::new(
+        randomizer.seed,
+        randomizer.randomizer,
+        1.0 / time.timestep().as_secs_f64(),
+    );
+    replay.top_tier_merge = *top_tier_merge;
+    replay.level = level.name.clone();
+    replay.level_fingerprint = level.fingerprint();
+    recorder.replay = replay;
+}
+
+fn record_input(input: Res<PlatformInput>, mut recorder: ResMut<ReplayRecorder>) {
+    if !recorder.stopped {
+        recorder.replay.push(*input);
+    }
+}
+
+/// The new game starts from a new item sequence, which the replay does not
+/// describe.
+fn stop_recording(mut recorder: ResMut<ReplayRecorder>) {
+    if !recorder.stopped {
+        info!("game restarted, stopped recording the replay");
+        recorder.stopped = true;
+    }
+}
+
+fn save_replay_on_exit(recorder: Res<ReplayRecorder>, mut exit_events: EventReader<AppExit>) {
+    if exit_events.read().count() == 0 {
+        return;
+    }
+    match recorder.save() {
+        Ok(()) => info!("saved replay to {:?}", recorder.path),
+        Err(error) => error!("{:?}: {error}", recorder.path),
+    }
+}
+
+fn stop_playing(mut player: ResMut<ReplayPlayer>) {
+    player.cursor = (player.replay.inputs.len(), 0);
+}
+
+/// Overrides the player input, which stays idle once the replay is over.
+fn play_input(mut player: ResMut<ReplayPlayer>, mut input: ResMut<PlatformInput>) {
+    *input = player.next_input().unwrap_or_default();
+}
@@ -0,0 +1,341 @@
+use bevy::{app::AppExit, ecs::event::ManualEventReader, prelude::*};
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    high_scores::RunStats,
+    items::ItemsResources,
+    physics::{AngularVelocity, Ball, InterpolatedTransform, Velocity},
+    platform::{ItemQueue, Platform, SpawnItemEvent, SpawnItemTimer},
+    randomizer::{ItemRandomizer, Randomizer},
+    scene::Level,
+    GameState, Score,
+};
+
+/// Version of the save format, raised when saves can no longer be read as
+/// the current `Snapshot`. `Snapshot::from_ron` is where older versions would
+/// be converted.
+pub const SAVE_VERSION: u32 = 2;
+/// Most items a saved randomizer may have drawn. Restoring replays every
+/// draw, so corrupt counts would otherwise hang the game at launch.
+pub const MAX_SAVED_DRAWS: u64 = 1_000_000;
+
+/// Restores an inserted `Snapshot` once the game is set up, and saves the
+/// game to the `SaveFile` when the app exits.
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        // After every startup system, so the level and the platform are in
+        // place.
+        app.add_systems(First, restore_snapshot.run_if(resource_exists::<Snapshot>));
+        app.add_systems(Last, save_on_exit);
+    }
+}
+
+/// State of a game in progress.
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    /// `Level::fingerprint` of the level played.
+    pub level_fingerprint: u64,
+    pub balls: Vec<SavedBall>,
+    pub platform_x: f32,
+    pub next_item: u8,
+    pub queue: Vec<u8>,
+    pub score: u32,
+    /// Time elapsed on `SpawnItemTimer` in seconds.
+    pub spawn_timer: f32,
+    pub randomizer: SavedRandomizer,
+    #[serde(default)]
+    pub highest_tier: u8,
+    /// Time played in seconds.
+    #[serde(default)]
+    pub duration: f32,
+}
+
+/// Fields added to `Ball` later need defaults here, so existing saves still
+/// load without a new version.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedBall {
+    pub item_type: u8,
+    pub translation: Vec3,
+    #[serde(default)]
+    pub rotation: Quat,
+    #[serde(default)]
+    pub velocity: Vec3,
+    #[serde(default)]
+    pub angular_velocity: f32,
+}
+
+/// Item sequence, replayed from its seed up to the number of items drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SavedRandomizer {
+    pub randomizer: Randomizer,
+    pub seed: u64,
+    pub draws: u64,
+}
+
+#[derive(Debug, Error)]
+pub enum SaveError {
+    #[error("could not access save: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse save: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("could not write save: {0}")]
+    Serialize(#[from] ron::Error),
+    #[error("invalid save: {0}")]
+    Invalid(String),
+    #[error("save does not match the game: {0}")]
+    Mismatch(String),
+}
+
+/// Leading field of every save version.
+#[derive(Deserialize)]
+struct SaveHeader {
+    version: u32,
+}
+
+impl Snapshot {
+    /// Captures the game of `world`.
+    pub fn capture(world: &mut World) -> Self {
+        let balls = world
+            .query::<(&Ball, &InterpolatedTransform, &Velocity, &AngularVelocity)>()
+            .iter(world)
+            .map(|(ball, transform, velocity, angular_velocity)| SavedBall {
+                item_type: ball.ball_type,
+                translation: transform.current,
+                rotation: transform.current_rotation,
+                velocity: velocity.velocity,
+                angular_velocity: angular_velocity.velocity,
+            })
+            .collect();
+        let (platform_x, next_item) = world
+            .query::<(&InterpolatedTransform, &Platform)>()
+            .iter(world)
+            .next()
+            .map_or((0.0, 0), |(transform, platform)| {
+                (transform.current.x, platform.next_item)
+            });
+        let randomizer = world.resource::<ItemRandomizer>();
+        let run_stats = world.resource::<RunStats>();
+        Self {
+            version: SAVE_VERSION,
+            level_fingerprint: world.resource::<Level>().fingerprint(),
+            balls,
+            platform_x,
+            next_item,
+            queue: world
+                .resource::<ItemQueue>()
+                .items
+                .iter()
+                .copied()
+                .collect(),
+            score: world.resource::<Score>().score,
+            spawn_timer: world.resource::<SpawnItemTimer>().timer.elapsed_secs(),
+            randomizer: SavedRandomizer {
+                randomizer: randomizer.randomizer,
+                seed: randomizer.seed,
+                draws: randomizer.draws(),
+            },
+            highest_tier: run_stats.highest_tier,
+            duration: run_stats.duration,
+        }
+    }
+
+    /// Replaces the game of `world` with the snapshot. The balls are spawned
+    /// with the next physics step.
+    pub fn restore(&self, world: &mut World) {
+        let balls = world
+            .query_filtered::<Entity, With<Ball>>()
+            .iter(world)
+            .collect::<Vec<_>>();
+        for entity in balls {
+            world.entity_mut(entity).despawn_recursive();
+        }
+        let mut spawn_item_events = world.resource_mut::<Events<SpawnItemEvent>>();
+        // Level items waiting to be spawned.
+        spawn_item_events.clear();
+        for ball in self.balls.iter() {
+            spawn_item_events.send(SpawnItemEvent {
+                item_type: ball.item_type,
+                position: ball.translation,
+                rotation: ball.rotation,
+                velocity: ball.velocity,
+                angular_velocity: ball.angular_velocity,
+                ..default()
+            });
+        }
+
+        for (mut transform, mut interpolated, mut platform) in world
+            .query::<(&mut Transform, &mut InterpolatedTransform, &mut Platform)>()
+            .iter_mut(world)
+        {
+            transform.translation.x = self.platform_x;
+            interpolated.previous = transform.translation;
+            interpolated.current = transform.translation;
+            platform.next_item = self.next_item;
+        }
+        world.resource_mut::<ItemQueue>().items = self.queue.iter().copied().collect();
+        world.resource_mut::<Score>().score = self.score;
+        world
+            .resource_mut::<SpawnItemTimer>()
+            .timer
+            .set_elapsed(Duration::from_secs_f32(self.spawn_timer));
+        let mut randomizer = ItemRandomizer::new(self.randomizer.randomizer, self.randomizer.seed);
+        randomizer.fast_forward(self.randomizer.draws, world.resource::<ItemsResources>());
+        world.insert_resource(randomizer);
+        world.insert_resource(RunStats {
+            highest_tier: self.highest_tier,
+            duration: self.duration,
+        });
+    }
+
+    /// Fails if the game was saved on another level, whose walls the items
+    /// would end up in.
+    pub fn check(&self, level: &Level) -> Result<(), SaveError> {
+        if self.level_fingerprint != level.fingerprint() {
+            return Err(SaveError::Mismatch(format!(
+                "saved on another level than {:?}",
+                level.name
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn from_ron(ron: &str) -> Result<Self, SaveError> {
+        let header: SaveHeader = ron::de::from_str(ron)?;
+        if header.version != SAVE_VERSION {
+            return Err(SaveError::Invalid(format!(
+                "version {} is not supported, expected {SAVE_VERSION}",
+                header.version
+            )));
+        }
+        let snapshot: Self = ron::de::from_str(ron)?;
+        if snapshot.randomizer.draws > MAX_SAVED_DRAWS {
+            return Err(SaveError::Invalid(format!(
+                "{} items drawn, at most {MAX_SAVED_DRAWS} are allowed",
+                snapshot.randomizer.draws
+            )));
+        }
+        // Restoring panics on a negative or non-finite timer, and the physics
+        // would spread bad values to every item they touch.
+        if !snapshot.spawn_timer.is_finite() || snapshot.spawn_timer < 0.0 {
+            return Err(SaveError::Invalid(format!(
+                "spawn timer {} is not a duration",
+                snapshot.spawn_timer
+            )));
+        }
+        if !snapshot.platform_x.is_finite() {
+            return Err(SaveError::Invalid(format!(
+                "platform position {} is not finite",
+                snapshot.platform_x
+            )));
+        }
+        if let Some(ball) = snapshot.balls.iter().find(|ball| {
+            !ball.translation.is_finite()
+                || !ball.rotation.is_finite()
+                || !ball.velocity.is_finite()
+                || !ball.angular_velocity.is_finite()
+        }) {
+            return Err(SaveError::Invalid(format!(
+                "item {ball:?} has a non-finite position or velocity"
+            )));
+        }
+        Ok(snapshot)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SaveError> {
+        Self::from_ron(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn to_ron(&self) -> Result<String, SaveError> {
+        let config = PrettyConfig::default().depth_limit(2);
+        Ok(ron::ser::to_string_pretty(self, config)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SaveError> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_ron()?)?;
+        Ok(())
+    }
+}
+
+/// File the game in progress is saved to when the app exits.
+#[derive(Resource, Debug, Clone)]
+pub struct SaveFile(pub PathBuf);
+
+impl SaveFile {
+    /// `save.ron` in the user data directory.
+    pub fn user() -> Option<Self> {
+        crate::data_dir().map(|dir| Self(dir.join("save.ron")))
+    }
+
+    /// Game saved on `level`, if any. Unreadable saves and saves of other
+    /// levels are reported and ignored, and replaced by the next save.
+    pub fn load(&self, level: &Level) -> Option<Snapshot> {
+        let result =
+            Snapshot::load(&self.0).and_then(|snapshot| snapshot.check(level).map(|()| snapshot));
+        match result {
+            Ok(snapshot) => Some(snapshot),
+            Err(SaveError::Io(error)) if error.kind() == std::io::ErrorKind::NotFound => None,
+            Err(error) => {
+                error!("{:?}: {error}", self.0);
+                None
+            }
+        }
+    }
+
+    /// Forgets the saved game.
+    pub fn remove(&self) -> Result<(), SaveError> {
+        match std::fs::remove_file(&self.0) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn restore_snapshot(world: &mut World) {
+    if let Some(snapshot) = world.remove_resource::<Snapshot>() {
+        snapshot.restore(world);
+    }
+}
+
+/// Saves games in progress, and removes the save of games that are over.
+fn save_on_exit(world: &mut World, mut exit_events: Local<ManualEventReader<AppExit>>) {
+    let exiting = exit_events
+        .read(world.resource::<Events<AppExit>>())
+        .count()
+        > 0;
+    let Some(file) = world.get_resource::<SaveFile>().cloned() else {
+        return;
+    };
+    if !exiting {
+        return;
+    }
+    let in_progress = matches!(
+        world
+            .get_resource::<State<GameState>>()
+            .map(|state| *state.get()),
+        Some(GameState::Playing | GameState::Paused)
+    );
+    let result = if in_progress {
+        Snapshot::capture(world).save(&file.0)
+    } else {
+        file.remove()
+    };
+    match result {
+        Ok(()) if in_progress => info!("saved game to {:?}", file.0),
+        Ok(()) => {}
+        Err(error) => error!("{:?}: {error}", file.0),
+    }
+}
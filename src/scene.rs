@@ -1,65 +1,433 @@
-use crate::physics::Rectangle;
-use bevy::prelude::*;
+use crate::{
+    game_over::DangerLine,
+    physics::{collider_aabb, Ball, Collider, InterpolatedTransform, PhysicsSystems},
+    platform::{PlatformBounds, SpawnItemEvent},
+    RestartEvent,
+};
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::{Indices, VertexAttributeValues},
+        render_asset::RenderAssetUsages,
+        render_resource::PrimitiveTopology,
+    },
+};
+use serde::Deserialize;
+use thiserror::Error;
 
-use std::f32::consts::PI;
+use std::{f32::consts::PI, path::Path};
 
-pub struct ScenePlugin;
+/// Level used when no other one is given.
+const CLASSIC_LEVEL: &str = include_str!("../assets/levels/classic.level.ron");
+/// Depth of collider meshes along Y.
+const COLLIDER_DEPTH: f32 = 20.0;
+
+#[derive(Default)]
+pub struct ScenePlugin {
+    pub level: Level,
+}
 
 impl Plugin for ScenePlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, spawn_scene);
+        app.insert_resource(self.level.platform_bounds());
+        app.insert_resource(self.level.clone());
+        app.add_systems(Startup, (spawn_scene, spawn_level_balls));
+        app.add_systems(Update, spawn_level_balls.run_if(on_event::<RestartEvent>()));
+        app.add_systems(
+            FixedUpdate,
+            despawn_escaped_items.after(PhysicsSystems::CollisionResolution),
+        );
+    }
+}
+
+/// Box around the colliders of the level. Items leaving it through the sides
+/// or the bottom have escaped the arena.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct ArenaBounds {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl ArenaBounds {
+    pub fn from_colliders<'a>(
+        colliders: impl IntoIterator<Item = (&'a Transform, &'a Collider)>,
+    ) -> Option<Self> {
+        colliders
+            .into_iter()
+            .map(|(transform, collider)| collider_aabb(collider, transform))
+            .reduce(|(min_1, max_1), (min_2, max_2)| (min_1.min(min_2), max_1.max(max_2)))
+            .map(|(min, max)| Self { min, max })
+    }
+
+    /// Whether an item at `position` is out of the arena. Items above it are
+    /// only falling in.
+    pub fn has_escaped(&self, position: Vec2) -> bool {
+        position.x < self.min.x || position.x > self.max.x || position.y < self.min.y
+    }
+}
+
+/// Arena description, authored as RON files like the ones in
+/// `assets/levels`. Positions are `(x, z)` pairs with the origin at the
+/// bottom left corner of the box.
+#[derive(Resource, Debug, Clone, Deserialize)]
+pub struct Level {
+    pub name: String,
+    /// Distance between the side walls.
+    pub width: f32,
+    /// Height of the side walls.
+    pub height: f32,
+    #[serde(default = "default_wall_thickness")]
+    pub wall_thickness: f32,
+    /// Static colliders inside the box, on top of its walls.
+    #[serde(default)]
+    pub colliders: Vec<LevelCollider>,
+    /// Platform placement, above the walls and between them if not set.
+    #[serde(default)]
+    pub platform: Option<LevelPlatform>,
+    /// Height of the danger line, the one of `GameOverPlugin` if not set.
+    #[serde(default)]
+    pub danger_line: Option<f32>,
+    /// Items in the box when the level starts.
+    #[serde(default)]
+    pub balls: Vec<LevelBall>,
+}
+
+fn default_wall_thickness() -> f32 {
+    5.0
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LevelCollider {
+    pub position: (f32, f32),
+    /// Counterclockwise rotation in degrees.
+    #[serde(default)]
+    pub rotation: f32,
+    pub shape: LevelShape,
+}
+
+/// Same shapes as `Collider`, with points as `(x, z)` pairs.
+#[derive(Debug, Clone, Deserialize)]
+pub enum LevelShape {
+    Rectangle { width: f32, height: f32 },
+    Circle { radius: f32 },
+    Polygon { points: Vec<(f32, f32)> },
+    Capsule { half_length: f32, radius: f32 },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LevelPlatform {
+    pub height: f32,
+    pub min_x: f32,
+    pub max_x: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LevelBall {
+    pub item_type: u8,
+    pub position: (f32, f32),
+}
+
+#[derive(Debug, Error)]
+pub enum LevelError {
+    #[error("could not read level: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse level: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("invalid level: {0}")]
+    Invalid(String),
+}
+
+impl Level {
+    pub fn from_ron(ron: &str) -> Result<Self, LevelError> {
+        let level: Self = ron::de::from_str(ron)?;
+        level.validate()?;
+        Ok(level)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LevelError> {
+        Self::from_ron(&std::fs::read_to_string(path)?)
+    }
+
+    /// Hash of the whole layout, which tells apart edited levels keeping
+    /// their name. FNV-1a, as the std hashers may change between builds.
+    pub fn fingerprint(&self) -> u64 {
+        format!("{self:?}")
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            })
+    }
+
+    fn validate(&self) -> Result<(), LevelError> {
+        let invalid = |message: String| Err(LevelError::Invalid(message));
+
+        let positive = |value: f32| value.is_finite() && value > 0.0;
+        if !positive(self.width) || !positive(self.height) || !positive(self.wall_thickness) {
+            return invalid(format!(
+                "box of {}x{} with {} thick walls",
+                self.width, self.height, self.wall_thickness
+            ));
+        }
+        for (i, collider) in self.colliders.iter().enumerate() {
+            let valid = match &collider.shape {
+                LevelShape::Rectangle { width, height } => positive(*width) && positive(*height),
+                LevelShape::Circle { radius } => positive(*radius),
+                LevelShape::Polygon { points } => is_convex_counterclockwise(points),
+                LevelShape::Capsule {
+                    half_length,
+                    radius,
+                } => positive(*half_length) && radius.is_finite() && *radius >= 0.0,
+            };
+            if !valid {
+                return invalid(format!("collider {i} has an invalid {:?}", collider.shape));
+            }
+        }
+        if let Some(platform) = &self.platform {
+            if platform.min_x > platform.max_x {
+                return invalid(format!(
+                    "platform bounds {}..{} are reversed",
+                    platform.min_x, platform.max_x
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn platform_bounds(&self) -> PlatformBounds {
+        match &self.platform {
+            Some(platform) => PlatformBounds {
+                height: platform.height,
+                min_x: platform.min_x,
+                max_x: platform.max_x,
+                walls: None,
+            },
+            None => PlatformBounds {
+                height: self.height,
+                min_x: 0.0,
+                max_x: self.width,
+                walls: None,
+            },
+        }
+    }
+
+    /// Walls of the box followed by the level colliders.
+    pub fn colliders(&self) -> Vec<(Transform, Collider)> {
+        let side_wall = Collider::Rectangle {
+            width: self.height,
+            height: self.wall_thickness,
+        };
+        let side_rotation = Quat::from_rotation_y(-PI / 2.0);
+
+        let mut colliders = vec![
+            // Left wall
+            (
+                Transform::from_xyz(0.0, 0.0, self.height / 2.0).with_rotation(side_rotation),
+                side_wall.clone(),
+            ),
+            // Right wall
+            (
+                Transform::from_xyz(self.width, 0.0, self.height / 2.0)
+                    .with_rotation(side_rotation),
+                side_wall,
+            ),
+            // Bottom wall
+            (
+                Transform::from_xyz(self.width / 2.0, 0.0, 0.0),
+                Collider::Rectangle {
+                    width: self.width,
+                    height: self.wall_thickness,
+                },
+            ),
+        ];
+
+        colliders.extend(self.colliders.iter().map(|collider| {
+            let (x, z) = collider.position;
+            let transform = Transform::from_xyz(x, 0.0, z)
+                .with_rotation(Quat::from_rotation_y(-collider.rotation.to_radians()));
+            let shape = match &collider.shape {
+                LevelShape::Rectangle { width, height } => Collider::Rectangle {
+                    width: *width,
+                    height: *height,
+                },
+                LevelShape::Circle { radius } => Collider::Circle { radius: *radius },
+                LevelShape::Polygon { points } => Collider::ConvexPolygon {
+                    points: points.iter().map(|(x, z)| Vec2::new(*x, *z)).collect(),
+                },
+                LevelShape::Capsule {
+                    half_length,
+                    radius,
+                } => Collider::Capsule {
+                    half_length: *half_length,
+                    radius: *radius,
+                },
+            };
+            (transform, shape)
+        }));
+        colliders
+    }
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self::from_ron(CLASSIC_LEVEL).expect("bundled level is valid")
+    }
+}
+
+fn is_convex_counterclockwise(points: &[(f32, f32)]) -> bool {
+    let points = points
+        .iter()
+        .map(|(x, z)| Vec2::new(*x, *z))
+        .collect::<Vec<_>>();
+    points.len() >= 3
+        && (0..points.len()).all(|i| {
+            let a = points[i];
+            let b = points[(i + 1) % points.len()];
+            let c = points[(i + 2) % points.len()];
+            (b - a).perp_dot(c - b) > 0.0
+        })
+}
+
+/// Mesh of a collider extruded along Y.
+fn collider_mesh(collider: &Collider) -> Mesh {
+    match collider {
+        Collider::Rectangle { width, height } => {
+            Cuboid::new(*width, COLLIDER_DEPTH, *height).mesh()
+        }
+        Collider::Circle { radius } => Cylinder::new(*radius, COLLIDER_DEPTH).mesh().build(),
+        Collider::ConvexPolygon { points } => polygon_prism_mesh(points),
+        Collider::Capsule {
+            half_length,
+            radius,
+        } => {
+            // Capsule meshes go along Y, so rotate the vertices onto X.
+            let mut mesh = Capsule3d {
+                radius: radius.max(0.5),
+                half_length: *half_length,
+            }
+            .mesh()
+            .build();
+            let rotation = Quat::from_rotation_z(-PI / 2.0);
+            for attribute in [Mesh::ATTRIBUTE_POSITION, Mesh::ATTRIBUTE_NORMAL] {
+                if let Some(VertexAttributeValues::Float32x3(values)) =
+                    mesh.attribute_mut(attribute)
+                {
+                    for value in values.iter_mut() {
+                        *value = (rotation * Vec3::from(*value)).to_array();
+                    }
+                }
+            }
+            mesh
+        }
     }
 }
 
+fn polygon_prism_mesh(points: &[Vec2]) -> Mesh {
+    let half_depth = COLLIDER_DEPTH / 2.0;
+    let mut positions = vec![];
+    // Front and back caps.
+    for y in [-half_depth, half_depth] {
+        for i in 1..points.len() - 1 {
+            let mut triangle = [points[0], points[i], points[i + 1]];
+            // Keep the winding pointing out of the prism.
+            if y > 0.0 {
+                triangle.swap(1, 2);
+            }
+            positions.extend(triangle.map(|p| [p.x, y, p.y]));
+        }
+    }
+    // Sides.
+    for i in 0..points.len() {
+        let a = points[i];
+        let b = points[(i + 1) % points.len()];
+        positions.extend([
+            [a.x, -half_depth, a.y],
+            [a.x, half_depth, a.y],
+            [b.x, -half_depth, b.y],
+            [b.x, -half_depth, b.y],
+            [a.x, half_depth, a.y],
+            [b.x, half_depth, b.y],
+        ]);
+    }
+
+    let indices = (0..positions.len() as u32).collect();
+    let mut mesh = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_indices(Indices::U32(indices));
+    mesh.compute_flat_normals();
+    mesh
+}
+
+/// Colliders only get meshes when rendering assets exist, so the scene can be
+/// spawned headless as well.
 fn spawn_scene(
+    level: Res<Level>,
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: Option<ResMut<Assets<Mesh>>>,
+    materials: Option<ResMut<Assets<StandardMaterial>>>,
+    danger_line: Option<ResMut<DangerLine>>,
 ) {
-    let mesh = meshes.add(Cuboid::new(100.0, 20.0, 5.0).mesh());
-    let material = materials.add(Color::WHITE);
-
-    // Left wall
-    let mut transform = Transform::from_rotation(Quat::from_rotation_y(-PI / 2.0));
-    transform.translation = Vec3::new(0.0, 0.0, 50.0);
-    commands
-        .spawn(PbrBundle {
-            mesh: mesh.clone(),
-            material: material.clone(),
-            transform,
-            ..default()
-        })
-        .insert(Rectangle {
-            width: 5.0,
-            height: 100.0,
-        });
+    let material = materials
+        .map(|mut materials| materials.add(Color::WHITE))
+        .unwrap_or_default();
 
-    // Right wall
-    let mut transform = Transform::from_rotation(Quat::from_rotation_y(-PI / 2.0));
-    transform.translation = Vec3::new(100.0, 0.0, 50.0);
-    commands
-        .spawn(PbrBundle {
-            mesh: mesh.clone(),
-            material: material.clone(),
-            transform,
-            ..default()
-        })
-        .insert(Rectangle {
-            width: 5.0,
-            height: 100.0,
-        });
+    let colliders = level.colliders();
+    if let Some(arena) = ArenaBounds::from_colliders(
+        colliders
+            .iter()
+            .map(|(transform, collider)| (transform, collider)),
+    ) {
+        commands.insert_resource(arena);
+    }
+
+    for (transform, collider) in colliders {
+        let mesh = meshes
+            .as_mut()
+            .map(|meshes| meshes.add(collider_mesh(&collider)))
+            .unwrap_or_default();
+        commands
+            .spawn(PbrBundle {
+                mesh,
+                material: material.clone(),
+                transform,
+                ..default()
+            })
+            .insert(collider);
+    }
+
+    if let (Some(height), Some(mut danger_line)) = (level.danger_line, danger_line) {
+        danger_line.height = height;
+    }
+}
 
-    // Bottom wall
-    let transform = Transform::from_translation(Vec3::new(50.0, 0.0, 0.0));
-    commands
-        .spawn(PbrBundle {
-            mesh: mesh.clone(),
-            material: material.clone(),
-            transform,
+fn spawn_level_balls(level: Res<Level>, mut spawn_item_events: EventWriter<SpawnItemEvent>) {
+    for ball in level.balls.iter() {
+        let (x, z) = ball.position;
+        spawn_item_events.send(SpawnItemEvent {
+            item_type: ball.item_type,
+            position: Vec3::new(x, 0.0, z),
             ..default()
-        })
-        .insert(Rectangle {
-            width: 100.0,
-            height: 5.0,
         });
+    }
+}
+
+fn despawn_escaped_items(
+    arena: Option<Res<ArenaBounds>>,
+    mut commands: Commands,
+    balls: Query<(Entity, &Ball, &InterpolatedTransform)>,
+) {
+    let Some(arena) = arena else {
+        return;
+    };
+    for (entity, ball, transform) in balls.iter() {
+        if arena.has_escaped(transform.current.xz()) {
+            warn!(
+                "item of type {} escaped the arena at {}",
+                ball.ball_type, transform.current
+            );
+            commands.entity(entity).despawn();
+        }
+    }
 }
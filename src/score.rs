@@ -0,0 +1,66 @@
+use bevy::prelude::*;
+
+use crate::{physics::PhysicsSystems, simulation_running};
+
+/// Time in seconds a merged item keeps its combo. Merging it again within
+/// that time continues the chain.
+pub const COMBO_WINDOW: f32 = 0.5;
+
+pub struct ScorePlugin;
+
+impl Plugin for ScorePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ScoreEvent>();
+        app.add_systems(
+            FixedUpdate,
+            tick_combos
+                .before(PhysicsSystems::CollisionDetection)
+                .run_if(simulation_running),
+        );
+    }
+}
+
+/// Sent for every merge that awards points.
+#[derive(Debug, Clone, Copy, Event)]
+pub struct ScoreEvent {
+    pub points: u32,
+    /// Tier of the created item, or of the vanished ones.
+    pub tier: u8,
+    /// Number of merges in the chain, 1 for a merge of two dropped items.
+    pub combo: u32,
+    /// Point where the items touched.
+    pub position: Vec3,
+}
+
+/// Chain of merges an item was created by, while it can still be continued.
+#[derive(Component, Debug)]
+pub struct Combo {
+    /// Number of merges in the chain, 0 for dropped items and once the combo
+    /// window is over.
+    pub depth: u32,
+    pub timer: Timer,
+}
+
+impl Combo {
+    pub fn new(depth: u32) -> Self {
+        Self {
+            depth,
+            timer: Timer::from_seconds(COMBO_WINDOW, TimerMode::Once),
+        }
+    }
+}
+
+/// Depth of the merge of two items. Items without a `Combo` count as dropped
+/// ones.
+pub fn merge_depth(combo_1: Option<&Combo>, combo_2: Option<&Combo>) -> u32 {
+    let depth = |combo: Option<&Combo>| combo.map_or(0, |combo| combo.depth);
+    depth(combo_1).max(depth(combo_2)) + 1
+}
+
+fn tick_combos(time: Res<Time>, mut combos: Query<&mut Combo>) {
+    for mut combo in combos.iter_mut() {
+        if combo.timer.tick(time.delta()).just_finished() {
+            combo.depth = 0;
+        }
+    }
+}
@@ -1,6 +1,34 @@
-use bevy::prelude::*;
+use bevy::{app::AppExit, prelude::*, window::ReceivedCharacter};
 
-use crate::Score;
+use crate::{
+    controls::{Action, Bindings, Remapping},
+    game_over::DangerLine,
+    high_scores::{HighScores, PendingHighScore, SubmitHighScoreEvent, INITIALS_LENGTH},
+    items::ItemsResources,
+    physics::{collider_frame, Ball, BroadPhase, Collider, PhysicsDebug, Sleeping, Velocity},
+    platform::{DropGuide, ItemQueue, Platform, PlatformSystems, QUEUE_LENGTH, SPAWN_OFFSET},
+    score::ScoreEvent,
+    GameState, RestartEvent, Score,
+};
+
+/// Number of times per second the danger indicator blinks.
+const DANGER_FLASH_RATE: f32 = 4.0;
+/// Time in seconds a score popup stays on screen.
+const POPUP_TIME: f32 = 1.0;
+/// Speed in pixels per second at which score popups rise.
+const POPUP_SPEED: f32 = 40.0;
+/// Line segments per full turn of the debug outlines.
+const DEBUG_SEGMENTS: usize = 32;
+/// Length of the contact normals drawn by the physics debug overlay.
+const DEBUG_NORMAL_LENGTH: f32 = 3.0;
+/// Seconds of motion shown by the velocity vectors of the physics debug
+/// overlay.
+const DEBUG_VELOCITY_SCALE: f32 = 0.1;
+/// Size in pixels of a unit of item radius in the next item preview.
+const PREVIEW_SCALE: f32 = 1.5;
+const BUTTON_COLOR: Color = Color::rgb(0.15, 0.15, 0.15);
+const BUTTON_HOVERED_COLOR: Color = Color::rgb(0.25, 0.25, 0.25);
+const BUTTON_PRESSED_COLOR: Color = Color::rgb(0.35, 0.35, 0.35);
 
 pub struct HudPlugin;
 
@@ -8,18 +36,112 @@ impl Plugin for HudPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, hud_setup);
         app.add_systems(Update, hud_update);
+        app.add_systems(Update, hud_danger_update);
+        app.add_systems(Update, hud_next_items_update);
+        app.add_systems(
+            Update,
+            (toggle_drop_guide, draw_drop_guide)
+                .chain()
+                .after(PlatformSystems::Interpolation)
+                .run_if(in_state(GameState::Playing)),
+        );
+        app.add_systems(
+            Update,
+            (spawn_score_popups, score_popups_update).run_if(not(in_state(GameState::Paused))),
+        );
+        app.add_systems(
+            Update,
+            (
+                toggle_physics_debug,
+                draw_physics_debug.run_if(|debug: Res<PhysicsDebug>| debug.enabled),
+            )
+                .chain(),
+        );
+        app.add_systems(Update, remapping_screen_update);
+        for state in [GameState::MainMenu, GameState::Paused, GameState::GameOver] {
+            app.add_systems(OnEnter(state), spawn_menu);
+            app.add_systems(OnExit(state), despawn_menus);
+        }
+        // Enter saves the initials of a new record before it can restart.
+        app.add_systems(
+            Update,
+            (
+                menu_buttons,
+                enter_initials.run_if(in_state(GameState::GameOver)),
+            )
+                .chain(),
+        );
+        app.add_systems(Update, high_scores_update);
     }
 }
 
 #[derive(Component)]
 struct UiScore;
 
+#[derive(Component)]
+struct UiDanger;
+
+/// Preview of the item dropped after the given number of drops.
+#[derive(Component)]
+struct UiNextItem(usize);
+
+/// Screen of the main menu, pause and game over states.
+#[derive(Component)]
+struct Menu;
+
+#[derive(Component, Debug, Clone, Copy)]
+enum MenuButton {
+    /// Starts a new game, as does `Restart`.
+    Play,
+    Resume,
+    Restart,
+    Controls,
+    MainMenu,
+    Quit,
+}
+
+impl MenuButton {
+    fn label(self) -> &'static str {
+        match self {
+            MenuButton::Play => "Play",
+            MenuButton::Resume => "Resume",
+            MenuButton::Restart => "Restart",
+            MenuButton::Controls => "Controls",
+            MenuButton::MainMenu => "Main menu",
+            MenuButton::Quit => "Quit",
+        }
+    }
+}
+
+/// High score table of the results screen, with the initials prompt.
+#[derive(Component)]
+struct UiHighScores;
+
+/// Controls remapping screen, see `Remapping`.
+#[derive(Component)]
+struct UiRemapping;
+
+/// Floating "+N" text shown where items merged.
+#[derive(Component)]
+struct ScorePopup {
+    timer: Timer,
+}
+
 fn hud_setup(asset_server: Res<AssetServer>, mut command: Commands) {
     let score_text_style = TextStyle {
         font: asset_server.load("fonts/monaco.ttf"),
         font_size: 20.0,
         color: Color::hex("faa307").unwrap(),
     };
+    let danger_text_style = TextStyle {
+        color: Color::hex("d00000").unwrap(),
+        ..score_text_style.clone()
+    };
+    let next_text_style = score_text_style.clone();
+    let remapping_text_style = TextStyle {
+        color: Color::WHITE,
+        ..score_text_style.clone()
+    };
 
     command
         .spawn(NodeBundle {
@@ -42,6 +164,53 @@ fn hud_setup(asset_server: Res<AssetServer>, mut command: Commands) {
                     ..default()
                 })
                 .insert(UiScore);
+            // danger
+            builder
+                .spawn(TextBundle {
+                    text: Text::from_section("DANGER", danger_text_style)
+                        .with_justify(JustifyText::Left),
+                    visibility: Visibility::Hidden,
+                    ..default()
+                })
+                .insert(UiDanger);
+            // next items
+            builder
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        align_items: AlignItems::Center,
+                        column_gap: Val::Px(4.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|builder| {
+                    builder.spawn(TextBundle::from_section("Next:", next_text_style));
+                    for i in 0..=QUEUE_LENGTH {
+                        builder.spawn(NodeBundle::default()).insert(UiNextItem(i));
+                    }
+                });
+        });
+
+    command
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.8).into(),
+            visibility: Visibility::Hidden,
+            // Over the menus it is opened from.
+            z_index: ZIndex::Global(1),
+            ..default()
+        })
+        .insert(UiRemapping)
+        .with_children(|builder| {
+            builder.spawn(TextBundle::from_section("", remapping_text_style));
         });
 }
 
@@ -50,3 +219,476 @@ fn hud_update(score: Res<Score>, mut ui_score: Query<&mut Text, With<UiScore>>)
     let str = format!("Score: {}", score.score);
     text.sections[0].value = str;
 }
+
+fn hud_next_items_update(
+    items_resources: Res<ItemsResources>,
+    item_queue: Res<ItemQueue>,
+    platform: Query<&Platform>,
+    mut ui_next_items: Query<(&UiNextItem, &mut Style, &mut BackgroundColor)>,
+) {
+    let Ok(platform) = platform.get_single() else {
+        return;
+    };
+    let next_items = std::iter::once(platform.next_item)
+        .chain(item_queue.items.iter().copied())
+        .collect::<Vec<_>>();
+    for (UiNextItem(i), mut style, mut background_color) in ui_next_items.iter_mut() {
+        let Some(item) = next_items
+            .get(*i)
+            .and_then(|item_type| items_resources.get(*item_type))
+        else {
+            style.display = Display::None;
+            continue;
+        };
+        let size = Val::Px(2.0 * item.radius * PREVIEW_SCALE);
+        style.display = Display::Flex;
+        style.width = size;
+        style.height = size;
+        *background_color = item.color.into();
+    }
+}
+
+fn remapping_screen_update(
+    remapping: Res<Remapping>,
+    bindings: Res<Bindings>,
+    mut ui_remapping: Query<(&mut Visibility, &Children), With<UiRemapping>>,
+    mut texts: Query<&mut Text>,
+) {
+    if !remapping.is_changed() && !bindings.is_changed() {
+        return;
+    }
+    let Ok((mut visibility, children)) = ui_remapping.get_single_mut() else {
+        return;
+    };
+    *visibility = if remapping.open {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    };
+    let Some(mut text) = children
+        .first()
+        .and_then(|child| texts.get_mut(*child).ok())
+    else {
+        return;
+    };
+    let mut str = String::from("Controls\n\n");
+    for action in Action::ALL {
+        let selected = action == remapping.selected;
+        let bound = if selected && remapping.listening {
+            if action.is_analog() {
+                "move a stick or click to follow the cursor...".to_string()
+            } else {
+                "press a key or button...".to_string()
+            }
+        } else {
+            let names = bindings
+                .get(action)
+                .iter()
+                .map(|binding| binding.to_string())
+                .collect::<Vec<_>>();
+            names.join(", ")
+        };
+        let cursor = if selected { ">" } else { " " };
+        str += &format!("{cursor} {:<10} {bound}\n", action.name());
+    }
+    str += "\nUp/Down: select  Enter: add  Backspace: clear  Delete: defaults  Esc: close";
+    text.sections[0].value = str;
+}
+
+fn toggle_drop_guide(keys: Res<ButtonInput<KeyCode>>, mut drop_guide: ResMut<DropGuide>) {
+    if keys.just_pressed(KeyCode::KeyG) {
+        drop_guide.enabled = !drop_guide.enabled;
+    }
+}
+
+/// Line from the platform down to where the next item lands, with its outline
+/// there.
+fn draw_drop_guide(
+    drop_guide: Res<DropGuide>,
+    items_resources: Res<ItemsResources>,
+    platform: Query<(&Transform, &Platform)>,
+    mut gizmos: Gizmos,
+) {
+    let (Some(landing), Ok((transform, platform))) = (drop_guide.landing, platform.get_single())
+    else {
+        return;
+    };
+    let Some(item) = items_resources.get(platform.next_item) else {
+        return;
+    };
+    let color = Color::rgba(1.0, 1.0, 1.0, 0.5);
+    gizmos.line(transform.translation + SPAWN_OFFSET, landing, color);
+    gizmos.circle(landing, Direction3d::Y, item.radius, color);
+}
+
+fn toggle_physics_debug(keys: Res<ButtonInput<KeyCode>>, mut debug: ResMut<PhysicsDebug>) {
+    if keys.just_pressed(KeyCode::F3) {
+        debug.enabled = !debug.enabled;
+    }
+}
+
+/// Collider and ball outlines, occupied broad phase cells, ball velocities
+/// and the contacts of the last physics step.
+fn draw_physics_debug(
+    debug: Res<PhysicsDebug>,
+    broad_phase: Res<BroadPhase>,
+    colliders: Query<(&Collider, &Transform)>,
+    balls: Query<(&Ball, &Transform, &Velocity, Has<Sleeping>)>,
+    mut gizmos: Gizmos,
+) {
+    let on_plane = |point: Vec2| Vec3::new(point.x, 0.0, point.y);
+
+    let cell_size = broad_phase.grid.cell_size();
+    for cell in broad_phase.grid.occupied_cells() {
+        let min = cell.as_vec2() * cell_size;
+        let corners = [
+            min,
+            min + Vec2::new(cell_size, 0.0),
+            min + cell_size,
+            min + Vec2::new(0.0, cell_size),
+            min,
+        ];
+        gizmos.linestrip(corners.map(on_plane), Color::DARK_GRAY);
+    }
+
+    for (collider, transform) in colliders.iter() {
+        let frame = collider_frame(transform);
+        let outline = collider.outline(DEBUG_SEGMENTS);
+        let first = outline.first().copied();
+        gizmos.linestrip(
+            outline
+                .into_iter()
+                .chain(first)
+                .map(|point| on_plane(frame.transform_point2(point))),
+            Color::GREEN,
+        );
+    }
+
+    for (ball, transform, velocity, sleeping) in balls.iter() {
+        let color = if sleeping { Color::GRAY } else { Color::CYAN };
+        gizmos
+            .circle(transform.translation, Direction3d::Y, ball.radius, color)
+            .segments(DEBUG_SEGMENTS);
+        gizmos.arrow(
+            transform.translation,
+            transform.translation + velocity.velocity * DEBUG_VELOCITY_SCALE,
+            Color::YELLOW,
+        );
+    }
+
+    for contact in debug.contacts.iter() {
+        let point = on_plane(contact.point);
+        gizmos.sphere(point, Quat::IDENTITY, 0.3, Color::RED);
+        gizmos.line(
+            point,
+            point + on_plane(contact.normal) * DEBUG_NORMAL_LENGTH,
+            Color::ORANGE_RED,
+        );
+    }
+}
+
+fn hud_danger_update(
+    time: Res<Time>,
+    state: Res<State<GameState>>,
+    danger_line: Res<DangerLine>,
+    mut ui_danger: Query<&mut Visibility, With<UiDanger>>,
+) {
+    let mut visibility = ui_danger.single_mut();
+    // The game over menu takes over once the game is lost.
+    let flash = (time.elapsed_seconds() * DANGER_FLASH_RATE).fract() < 0.5;
+    let visible = *state.get() == GameState::Playing && danger_line.in_danger() && flash;
+    *visibility = if visible {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    };
+}
+
+fn spawn_score_popups(
+    asset_server: Res<AssetServer>,
+    mut score_events: EventReader<ScoreEvent>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut commands: Commands,
+) {
+    let Ok((camera, camera_transform)) = cameras.get_single() else {
+        return;
+    };
+    for event in score_events.read() {
+        let Some(position) = camera.world_to_viewport(camera_transform, event.position) else {
+            continue;
+        };
+        let str = if event.combo > 1 {
+            format!("+{} x{}", event.points, event.combo)
+        } else {
+            format!("+{}", event.points)
+        };
+        let style = TextStyle {
+            font: asset_server.load("fonts/monaco.ttf"),
+            font_size: 16.0 + 4.0 * event.combo.min(4) as f32,
+            color: Color::hex("faa307").unwrap(),
+        };
+        commands
+            .spawn(TextBundle {
+                text: Text::from_section(str, style),
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    left: Val::Px(position.x),
+                    top: Val::Px(position.y),
+                    ..default()
+                },
+                ..default()
+            })
+            .insert(ScorePopup {
+                timer: Timer::from_seconds(POPUP_TIME, TimerMode::Once),
+            });
+    }
+}
+
+/// Makes the popups rise and fade out.
+fn score_popups_update(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut popups: Query<(Entity, &mut ScorePopup, &mut Style, &mut Text)>,
+) {
+    for (entity, mut popup, mut style, mut text) in popups.iter_mut() {
+        if popup.timer.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        if let Val::Px(top) = style.top {
+            style.top = Val::Px(top - POPUP_SPEED * time.delta_seconds());
+        }
+        let alpha = 1.0 - popup.timer.fraction();
+        for section in text.sections.iter_mut() {
+            section.style.color.set_a(alpha);
+        }
+    }
+}
+
+fn spawn_menu(
+    asset_server: Res<AssetServer>,
+    state: Res<State<GameState>>,
+    score: Res<Score>,
+    mut commands: Commands,
+) {
+    let (title, buttons) = match state.get() {
+        GameState::MainMenu => (
+            "COMBOBOX".to_string(),
+            vec![MenuButton::Play, MenuButton::Controls, MenuButton::Quit],
+        ),
+        GameState::Paused => (
+            "PAUSED".to_string(),
+            vec![
+                MenuButton::Resume,
+                MenuButton::Restart,
+                MenuButton::Controls,
+                MenuButton::MainMenu,
+            ],
+        ),
+        GameState::GameOver => (
+            format!("GAME OVER\nScore: {}", score.score),
+            vec![MenuButton::Restart, MenuButton::MainMenu],
+        ),
+        GameState::Playing => return,
+    };
+    let font = asset_server.load("fonts/monaco.ttf");
+    let title_style = TextStyle {
+        font: font.clone(),
+        font_size: 40.0,
+        color: Color::hex("faa307").unwrap(),
+    };
+    let button_style = TextStyle {
+        font,
+        font_size: 20.0,
+        color: Color::WHITE,
+    };
+    let high_scores_style = TextStyle {
+        font_size: 16.0,
+        ..button_style.clone()
+    };
+    let results = *state.get() == GameState::GameOver;
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                row_gap: Val::Px(12.0),
+                ..default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+            ..default()
+        })
+        .insert(Menu)
+        .with_children(|builder| {
+            builder.spawn(
+                TextBundle::from_section(title, title_style).with_text_justify(JustifyText::Center),
+            );
+            if results {
+                builder
+                    .spawn(TextBundle::from_section("", high_scores_style))
+                    .insert(UiHighScores);
+            }
+            for button in buttons {
+                builder
+                    .spawn(ButtonBundle {
+                        style: Style {
+                            width: Val::Px(200.0),
+                            padding: UiRect::all(Val::Px(8.0)),
+                            justify_content: JustifyContent::Center,
+                            ..default()
+                        },
+                        background_color: BUTTON_COLOR.into(),
+                        ..default()
+                    })
+                    .insert(button)
+                    .with_children(|builder| {
+                        builder.spawn(TextBundle::from_section(
+                            button.label(),
+                            button_style.clone(),
+                        ));
+                    });
+            }
+        });
+}
+
+fn despawn_menus(mut commands: Commands, menus: Query<Entity, With<Menu>>) {
+    for entity in menus.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Buttons are clicked, or Enter picks the first one of the menu.
+#[allow(clippy::too_many_arguments)]
+fn menu_buttons(
+    keys: Res<ButtonInput<KeyCode>>,
+    state: Res<State<GameState>>,
+    pending: Res<PendingHighScore>,
+    mut remapping: ResMut<Remapping>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut restart_events: EventWriter<RestartEvent>,
+    mut exit_events: EventWriter<AppExit>,
+    mut buttons: Query<(&Interaction, &MenuButton, &mut BackgroundColor), Changed<Interaction>>,
+) {
+    if remapping.open {
+        return;
+    }
+    let mut pressed = None;
+    for (interaction, button, mut background_color) in buttons.iter_mut() {
+        *background_color = match interaction {
+            Interaction::Pressed => {
+                pressed = Some(*button);
+                BUTTON_PRESSED_COLOR
+            }
+            Interaction::Hovered => BUTTON_HOVERED_COLOR,
+            Interaction::None => BUTTON_COLOR,
+        }
+        .into();
+    }
+    if keys.just_pressed(KeyCode::Enter) && pending.entry.is_none() {
+        pressed = match state.get() {
+            GameState::MainMenu => Some(MenuButton::Play),
+            GameState::GameOver => Some(MenuButton::Restart),
+            GameState::Paused => Some(MenuButton::Resume),
+            GameState::Playing => None,
+        };
+    }
+
+    match pressed {
+        Some(MenuButton::Play | MenuButton::Restart) => {
+            restart_events.send(RestartEvent);
+        }
+        Some(MenuButton::Resume) => next_state.set(GameState::Playing),
+        Some(MenuButton::Controls) => remapping.open = true,
+        Some(MenuButton::MainMenu) => next_state.set(GameState::MainMenu),
+        Some(MenuButton::Quit) => {
+            exit_events.send(AppExit);
+        }
+        None => {}
+    }
+}
+
+fn high_scores_update(
+    high_scores: Res<HighScores>,
+    pending: Res<PendingHighScore>,
+    added: Query<(), Added<UiHighScores>>,
+    mut ui_high_scores: Query<&mut Text, With<UiHighScores>>,
+) {
+    if !high_scores.is_changed() && !pending.is_changed() && added.is_empty() {
+        return;
+    }
+    let mut str = String::new();
+    if let Some(entry) = &pending.entry {
+        str += &format!(
+            "New record! Initials: {:_<width$} (Enter)\n\n",
+            entry.initials,
+            width = INITIALS_LENGTH
+        );
+    }
+    if high_scores.entries.is_empty() {
+        str += "No high scores yet";
+    }
+    for (rank, entry) in high_scores.entries.iter().enumerate() {
+        let cursor = if pending.last_rank == Some(rank) {
+            ">"
+        } else {
+            " "
+        };
+        let duration = entry.duration as u32;
+        str += &format!(
+            "{cursor}{:>2}. {:<3} {:>7}  tier {:<2} {:>2}:{:02}  {}\n",
+            rank + 1,
+            entry.initials,
+            entry.score,
+            entry.highest_tier + 1,
+            duration / 60,
+            duration % 60,
+            entry.date_string()
+        );
+    }
+    for mut text in ui_high_scores.iter_mut() {
+        text.sections[0].value = str.clone();
+    }
+}
+
+/// Letters and digits typed while a record waits for its initials, in the
+/// keyboard layout of the player.
+fn enter_initials(
+    keys: Res<ButtonInput<KeyCode>>,
+    remapping: Res<Remapping>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut pending: ResMut<PendingHighScore>,
+    mut submit_events: EventWriter<SubmitHighScoreEvent>,
+) {
+    let typed = characters
+        .read()
+        .flat_map(|event| event.char.chars())
+        .filter(char::is_ascii_alphanumeric)
+        .collect::<Vec<_>>();
+    if remapping.open {
+        return;
+    }
+    let Some(entry) = pending.entry.as_ref() else {
+        return;
+    };
+    let mut initials = entry.initials.clone();
+    if keys.just_pressed(KeyCode::Backspace) {
+        initials.pop();
+    }
+    for character in typed {
+        if initials.len() < INITIALS_LENGTH {
+            initials.push(character.to_ascii_uppercase());
+        }
+    }
+    if keys.just_pressed(KeyCode::Enter) {
+        submit_events.send(SubmitHighScoreEvent { initials });
+    } else if initials != entry.initials {
+        if let Some(entry) = pending.entry.as_mut() {
+            entry.initials = initials;
+        }
+    }
+}
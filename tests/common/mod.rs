@@ -0,0 +1,48 @@
+//! Fixtures shared by the integration tests. Each test file only uses some
+//! of them.
+#![allow(dead_code)]
+
+use bevy::{
+    input::{
+        keyboard::{Key, KeyboardInput, NativeKey},
+        ButtonState,
+    },
+    prelude::*,
+};
+
+use combobox::headless_app;
+
+pub const TICK_RATE: f64 = 64.0;
+
+/// Headless game, started.
+pub fn game_app() -> App {
+    start(headless_app(TICK_RATE))
+}
+
+/// Runs the first update, which spawns the scene and initializes the clocks
+/// without advancing them.
+pub fn start(mut app: App) -> App {
+    app.update();
+    app
+}
+
+/// Runs as many physics steps as fit in `seconds`, one per update.
+pub fn run_for(app: &mut App, seconds: f64) {
+    for _ in 0..(seconds * TICK_RATE) as u32 {
+        app.update();
+    }
+}
+
+/// Presses and releases a key through input events, so it counts as just
+/// pressed for a frame.
+pub fn tap(app: &mut App, key_code: KeyCode) {
+    for state in [ButtonState::Pressed, ButtonState::Released] {
+        app.world.send_event(KeyboardInput {
+            key_code,
+            logical_key: Key::Unidentified(NativeKey::Unidentified),
+            state,
+            window: Entity::PLACEHOLDER,
+        });
+        app.update();
+    }
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use bevy::{
+    input::{
+        gamepad::{GamepadConnection, GamepadConnectionEvent, GamepadInfo},
+        mouse::MouseButtonInput,
+        ButtonState,
+    },
+    prelude::*,
+};
+
+use combobox::{
+    controls::{Action, Binding, Bindings, BindingsFile, Remapping},
+    physics::{Ball, InterpolatedTransform},
+    platform::{Platform, PlatformInput},
+};
+use common::{game_app, run_for, tap};
+
+/// Position of the last physics step, the rendered one lags behind.
+fn platform_x(app: &mut App) -> f32 {
+    app.world
+        .query_filtered::<&InterpolatedTransform, With<Platform>>()
+        .single(&app.world)
+        .current
+        .x
+}
+
+#[test]
+fn actions_follow_their_bindings() {
+    let mut app = game_app();
+    app.world.resource_mut::<Bindings>().move_right = vec![Binding::Key(KeyCode::KeyL)];
+    let start = platform_x(&mut app);
+
+    let mut keys = app.world.resource_mut::<ButtonInput<KeyCode>>();
+    keys.press(KeyCode::KeyD);
+    keys.press(KeyCode::Space);
+    app.update();
+    assert_eq!(
+        *app.world.resource::<PlatformInput>(),
+        PlatformInput {
+            movement: 0.0,
+            drop: true
+        }
+    );
+    assert_eq!(platform_x(&mut app), start);
+
+    app.world
+        .resource_mut::<ButtonInput<KeyCode>>()
+        .press(KeyCode::KeyL);
+    app.update();
+    assert_eq!(app.world.resource::<PlatformInput>().movement, 1.0);
+    assert!(platform_x(&mut app) > start);
+}
+
+#[test]
+fn clicks_drop_one_item() {
+    let mut app = game_app();
+    let items = |app: &mut App| app.world.query::<&Ball>().iter(&app.world).count();
+    run_for(&mut app, 0.5);
+    let before = items(&mut app);
+
+    // Released within the same frame, and before the platform is ready.
+    for state in [ButtonState::Pressed, ButtonState::Released] {
+        app.world.send_event(MouseButtonInput {
+            button: MouseButton::Left,
+            state,
+            window: Entity::PLACEHOLDER,
+        });
+    }
+    run_for(&mut app, 2.0);
+    assert_eq!(items(&mut app), before + 1);
+}
+
+#[test]
+fn sticks_move_the_platform_proportionally() {
+    let mut app = game_app();
+    let gamepad = Gamepad::new(0);
+    app.world.send_event(GamepadConnectionEvent::new(
+        gamepad,
+        GamepadConnection::Connected(GamepadInfo {
+            name: "Test".to_string(),
+        }),
+    ));
+    app.update();
+    let axis = GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX);
+
+    app.world
+        .resource_mut::<Axis<GamepadAxis>>()
+        .set(axis, -0.5);
+    app.update();
+    assert_eq!(app.world.resource::<PlatformInput>().movement, -0.5);
+
+    // Within the dead zone
+    app.world.resource_mut::<Axis<GamepadAxis>>().set(axis, 0.1);
+    app.update();
+    assert_eq!(app.world.resource::<PlatformInput>().movement, 0.0);
+}
+
+#[test]
+fn bindings_are_named_after_their_keys() {
+    let names = [
+        Binding::Key(KeyCode::KeyQ),
+        Binding::Key(KeyCode::Digit7),
+        Binding::Key(KeyCode::Numpad7),
+        Binding::Key(KeyCode::ArrowLeft),
+        Binding::Key(KeyCode::Space),
+        Binding::Mouse(MouseButton::Left),
+    ]
+    .map(|binding| binding.to_string());
+    assert_eq!(names, ["Q", "7", "Num 7", "Left", "Space", "Mouse Left"]);
+}
+
+#[test]
+fn remapping_binds_the_next_input() {
+    let mut app = game_app();
+    tap(&mut app, KeyCode::F1);
+    assert!(app.world.resource::<Remapping>().open);
+    tap(&mut app, KeyCode::ArrowDown);
+    tap(&mut app, KeyCode::ArrowDown);
+    assert_eq!(app.world.resource::<Remapping>().selected, Action::Drop);
+
+    tap(&mut app, KeyCode::Backspace);
+    tap(&mut app, KeyCode::Enter);
+    assert!(app.world.resource::<Remapping>().listening);
+    tap(&mut app, KeyCode::KeyJ);
+    assert!(!app.world.resource::<Remapping>().listening);
+    assert_eq!(
+        app.world.resource::<Bindings>().drop,
+        vec![Binding::Key(KeyCode::KeyJ)]
+    );
+
+    // No input reaches the platform while remapping.
+    app.world
+        .resource_mut::<ButtonInput<KeyCode>>()
+        .press(KeyCode::KeyJ);
+    app.update();
+    assert!(!app.world.resource::<PlatformInput>().drop);
+
+    tap(&mut app, KeyCode::Escape);
+    assert!(!app.world.resource::<Remapping>().open);
+    app.world
+        .resource_mut::<ButtonInput<KeyCode>>()
+        .press(KeyCode::KeyJ);
+    app.update();
+    assert!(app.world.resource::<PlatformInput>().drop);
+}
+
+#[test]
+fn bindings_files_round_trip() {
+    let path = std::env::temp_dir().join(format!("combobox-{}.bindings.ron", std::process::id()));
+    let file = BindingsFile(path.clone());
+    assert_eq!(file.load().unwrap(), Bindings::default());
+
+    let bindings = Bindings {
+        aim: vec![Binding::Cursor],
+        dead_zone: 0.3,
+        ..default()
+    };
+    bindings.save(&path).unwrap();
+    assert_eq!(file.load().unwrap(), bindings);
+
+    // Missing actions keep their defaults.
+    std::fs::write(&path, "(drop: [Key(KeyK)])").unwrap();
+    let loaded = file.load().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.drop, vec![Binding::Key(KeyCode::KeyK)]);
+    assert_eq!(loaded.move_left, Bindings::default().move_left);
+}
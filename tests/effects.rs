@@ -0,0 +1,82 @@
+mod common;
+
+use bevy::prelude::*;
+
+use combobox::{
+    effects::{ease_out, MergeTween, Particle, ScaleIn, PARTICLE_TIME},
+    physics::{Ball, MergeEvent},
+    platform::SpawnItemEvent,
+};
+use common::{game_app, TICK_RATE};
+
+/// Game with two items of the first tier about to merge on the floor.
+fn merging_app() -> App {
+    let mut app = game_app();
+    for x in [40.0, 44.0] {
+        app.world.send_event(SpawnItemEvent {
+            item_type: 0,
+            position: Vec3::new(x, 0.0, 20.0),
+            ..default()
+        });
+    }
+    app
+}
+
+fn count<T: Component>(app: &mut App) -> usize {
+    app.world.query::<&T>().iter(&app.world).count()
+}
+
+#[test]
+fn merges_send_merge_events() {
+    let mut app = merging_app();
+    let mut reader = app.world.resource::<Events<MergeEvent>>().get_reader();
+    let mut merges = vec![];
+    for _ in 0..10 {
+        app.update();
+        merges.extend(reader.read(app.world.resource()).copied());
+    }
+    assert_eq!(merges.len(), 1, "{merges:?}");
+    let merge = merges[0];
+    assert_eq!((merge.item_type, merge.merged_type), (0, Some(1)));
+    let [a, b] = merge.sources.map(|source| source.translation);
+    assert!(merge.position.distance(a.lerp(b, 0.5)) < 1.0, "{merge:?}");
+}
+
+#[test]
+fn merge_effects_play_and_clean_up() {
+    let mut app = merging_app();
+    while count::<MergeTween>(&mut app) == 0 {
+        app.update();
+    }
+    assert_eq!(count::<MergeTween>(&mut app), 2);
+    assert!(count::<Particle>(&mut app) > 0);
+    // Tweened copies of the merged items are not items.
+    assert_eq!(count::<Ball>(&mut app), 1);
+
+    // The merged item grows while its collider keeps the full size.
+    let item = app.world.query::<&ScaleIn>().single(&app.world).item;
+    let (ball, transform) = app
+        .world
+        .query::<(&Ball, &Transform)>()
+        .get(&app.world, item)
+        .unwrap();
+    let radius = ball.radius;
+    assert!(transform.scale.x < 1.0);
+
+    for _ in 0..(PARTICLE_TIME as f64 * TICK_RATE) as u32 + 1 {
+        app.update();
+    }
+    assert_eq!(count::<MergeTween>(&mut app), 0);
+    assert_eq!(count::<Particle>(&mut app), 0);
+    assert_eq!(count::<ScaleIn>(&mut app), 0);
+    let (ball, transform) = app.world.query::<(&Ball, &Transform)>().single(&app.world);
+    assert_eq!((ball.radius, transform.scale), (radius, Vec3::ONE));
+}
+
+#[test]
+fn ease_out_starts_fast_and_settles() {
+    assert_eq!(ease_out(0.0), 0.0);
+    assert_eq!(ease_out(1.0), 1.0);
+    assert_eq!(ease_out(2.0), 1.0);
+    assert!(ease_out(0.5) > 0.5);
+}
@@ -0,0 +1,198 @@
+mod common;
+
+use std::time::Duration;
+
+use bevy::{ecs::event::ManualEventReader, prelude::*, time::TimeUpdateStrategy};
+
+use combobox::{
+    game_over::{DangerLine, GameOverEvent},
+    items::ItemsResources,
+    physics::{Ball, InterpolatedTransform},
+    platform::{Platform, SpawnItemEvent},
+    GameState, RestartEvent, Score,
+};
+use common::{game_app, run_for, tap, TICK_RATE};
+
+fn drop_item(app: &mut App, x: f32, z: f32) {
+    app.world.send_event(SpawnItemEvent {
+        item_type: 1,
+        position: Vec3::new(x, 0.0, z),
+        ..default()
+    });
+}
+
+/// Lowers the line so a single item resting on the floor crosses it.
+fn lower_danger_line(app: &mut App) {
+    let radius = app.world.resource::<ItemsResources>().tiers[1].radius;
+    app.world.resource_mut::<DangerLine>().height = 2.5 + radius;
+}
+
+fn state(app: &App) -> GameState {
+    *app.world.resource::<State<GameState>>().get()
+}
+
+fn ball_positions(app: &mut App) -> Vec<Vec3> {
+    app.world
+        .query_filtered::<&InterpolatedTransform, With<Ball>>()
+        .iter(&app.world)
+        .map(|transform| transform.current)
+        .collect()
+}
+
+/// Rendered positions, interpolated between the physics steps.
+fn rendered_positions(app: &mut App) -> Vec<Vec3> {
+    app.world
+        .query_filtered::<&Transform, With<Ball>>()
+        .iter(&app.world)
+        .map(|transform| transform.translation)
+        .collect()
+}
+
+fn ball_count(app: &mut App) -> usize {
+    app.world.query::<&Ball>().iter(&app.world).count()
+}
+
+#[test]
+fn falling_through_the_danger_line_is_safe() {
+    let mut app = game_app();
+    drop_item(&mut app, 50.0, 95.0);
+    run_for(&mut app, 5.0);
+
+    assert_eq!(state(&app), GameState::Playing);
+    assert!(!app.world.resource::<DangerLine>().in_danger());
+}
+
+#[test]
+fn items_count_only_once_they_landed() {
+    let mut app = game_app();
+    // Dropped from rest, above the line.
+    drop_item(&mut app, 50.0, 95.0);
+    for _ in 0..10 {
+        app.update();
+        assert!(!app.world.resource::<DangerLine>().in_danger());
+    }
+}
+
+#[test]
+fn resting_above_the_danger_line_ends_the_game_after_the_grace_time() {
+    let mut app = game_app();
+    let mut reader = ManualEventReader::<GameOverEvent>::default();
+    lower_danger_line(&mut app);
+    drop_item(&mut app, 50.0, 30.0);
+
+    run_for(&mut app, 1.0);
+    assert!(app.world.resource::<DangerLine>().in_danger());
+    assert_eq!(state(&app), GameState::Playing);
+
+    let mut events = 0;
+    for _ in 0..(3.0 * TICK_RATE) as u32 {
+        app.update();
+        events += reader
+            .read(app.world.resource::<Events<GameOverEvent>>())
+            .count();
+    }
+    assert_eq!(state(&app), GameState::GameOver);
+    assert_eq!(events, 1);
+}
+
+#[test]
+fn leaving_the_danger_zone_resets_the_timer() {
+    let mut app = game_app();
+    lower_danger_line(&mut app);
+    drop_item(&mut app, 50.0, 30.0);
+    run_for(&mut app, 1.0);
+    assert!(app.world.resource::<DangerLine>().in_danger());
+
+    app.world.resource_mut::<DangerLine>().height = 50.0;
+    run_for(&mut app, 0.1);
+    assert!(!app.world.resource::<DangerLine>().in_danger());
+    run_for(&mut app, 3.0);
+    assert_eq!(state(&app), GameState::Playing);
+}
+
+#[test]
+fn spawning_is_disabled_after_game_over() {
+    let mut app = game_app();
+    lower_danger_line(&mut app);
+    drop_item(&mut app, 50.0, 30.0);
+    run_for(&mut app, 3.0);
+    assert_eq!(state(&app), GameState::GameOver);
+    assert_eq!(ball_count(&mut app), 1);
+
+    app.world
+        .resource_mut::<ButtonInput<KeyCode>>()
+        .press(KeyCode::Space);
+    run_for(&mut app, 3.0);
+    assert_eq!(ball_count(&mut app), 1);
+}
+
+#[test]
+fn pausing_freezes_the_game() {
+    let mut app = game_app();
+    drop_item(&mut app, 50.0, 80.0);
+    run_for(&mut app, 0.2);
+
+    tap(&mut app, KeyCode::Escape);
+    assert_eq!(state(&app), GameState::Paused);
+    let positions = ball_positions(&mut app);
+    let rendered = rendered_positions(&mut app);
+    app.world
+        .resource_mut::<ButtonInput<KeyCode>>()
+        .press(KeyCode::Space);
+    // Frames between the physics steps keep the rendered balls still.
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+        1.5 / TICK_RATE,
+    )));
+    for _ in 0..10 {
+        app.update();
+        assert_eq!(rendered_positions(&mut app), rendered);
+    }
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+        1.0 / TICK_RATE,
+    )));
+    run_for(&mut app, 2.0);
+    assert_eq!(ball_positions(&mut app), positions);
+
+    app.world
+        .resource_mut::<ButtonInput<KeyCode>>()
+        .release(KeyCode::Space);
+    tap(&mut app, KeyCode::Escape);
+    assert_eq!(state(&app), GameState::Playing);
+    run_for(&mut app, 0.2);
+    assert_ne!(ball_positions(&mut app), positions);
+}
+
+#[test]
+fn restarting_starts_a_new_game() {
+    let mut app = game_app();
+    let start = app
+        .world
+        .query_filtered::<&Transform, With<Platform>>()
+        .single(&app.world)
+        .translation;
+    lower_danger_line(&mut app);
+    drop_item(&mut app, 50.0, 30.0);
+    app.world
+        .resource_mut::<ButtonInput<KeyCode>>()
+        .press(KeyCode::KeyD);
+    run_for(&mut app, 3.0);
+    app.world.resource_mut::<Score>().score = 100;
+    assert_eq!(state(&app), GameState::GameOver);
+
+    app.world.send_event(RestartEvent);
+    app.world
+        .resource_mut::<ButtonInput<KeyCode>>()
+        .release(KeyCode::KeyD);
+    // The state changes on the frame after the restart.
+    run_for(&mut app, 2.0 / TICK_RATE);
+    assert_eq!(state(&app), GameState::Playing);
+    assert_eq!(ball_count(&mut app), 0);
+    assert_eq!(app.world.resource::<Score>().score, 0);
+    assert!(!app.world.resource::<DangerLine>().in_danger());
+    let platform = app
+        .world
+        .query_filtered::<&Transform, With<Platform>>()
+        .single(&app.world)
+        .translation;
+    assert_eq!(platform, start);
+}
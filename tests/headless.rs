@@ -0,0 +1,49 @@
+mod common;
+
+use bevy::prelude::*;
+
+use combobox::{
+    items::ItemsResources,
+    physics::{Ball, InterpolatedTransform},
+    platform::Platform,
+    Score,
+};
+use common::{game_app, run_for};
+
+#[test]
+fn headless_app_runs_without_rendering_assets() {
+    let mut app = game_app();
+
+    assert!(!app.world.contains_resource::<Assets<Mesh>>());
+    assert!(!app.world.contains_resource::<Assets<StandardMaterial>>());
+    assert!(app.world.contains_resource::<ItemsResources>());
+    assert_eq!(app.world.resource::<Score>().score, 0);
+    let platforms = app.world.query::<&Platform>().iter(&app.world).count();
+    assert_eq!(platforms, 1);
+}
+
+#[test]
+fn dropped_items_land_inside_the_box() {
+    let mut app = game_app();
+
+    app.world
+        .resource_mut::<ButtonInput<KeyCode>>()
+        .press(KeyCode::Space);
+    run_for(&mut app, 1.5);
+    app.world
+        .resource_mut::<ButtonInput<KeyCode>>()
+        .release(KeyCode::Space);
+    run_for(&mut app, 3.0);
+
+    let balls = app
+        .world
+        .query::<(&Ball, &InterpolatedTransform)>()
+        .iter(&app.world)
+        .map(|(ball, transform)| (ball.radius, transform.current))
+        .collect::<Vec<_>>();
+    assert_eq!(balls.len(), 1);
+    for (radius, position) in balls {
+        assert!(position.x > radius && position.x < 100.0 - radius);
+        assert!((position.z - (2.5 + radius)).abs() < 0.5, "{position}");
+    }
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use bevy::prelude::*;
+
+use combobox::{
+    game_over::DangerLine,
+    high_scores::{
+        HighScore, HighScores, HighScoresFile, PendingHighScore, SubmitHighScoreEvent,
+        HIGH_SCORE_COUNT,
+    },
+    items::ItemsResources,
+    platform::SpawnItemEvent,
+    GameState, RestartEvent,
+};
+use common::{game_app, run_for};
+
+fn entry(score: u32) -> HighScore {
+    HighScore {
+        initials: "ABC".to_string(),
+        score,
+        highest_tier: 2,
+        duration: 61.5,
+        seed: 7,
+        date: 0,
+    }
+}
+
+fn temp_file(name: &str) -> HighScoresFile {
+    HighScoresFile(std::env::temp_dir().join(format!(
+        "combobox-{}-{name}.high_scores.ron",
+        std::process::id()
+    )))
+}
+
+/// Runs a game ending with a single item resting above a lowered danger
+/// line, after a merge scored its points.
+fn lose_game(app: &mut App) {
+    let radius = app.world.resource::<ItemsResources>().tiers[1].radius;
+    app.world.resource_mut::<DangerLine>().height = 2.5 + radius;
+    for z in [10.0, 30.0] {
+        app.world.send_event(SpawnItemEvent {
+            item_type: 0,
+            position: Vec3::new(50.0, 0.0, z),
+            ..default()
+        });
+    }
+    run_for(app, 4.0);
+    assert_eq!(
+        *app.world.resource::<State<GameState>>().get(),
+        GameState::GameOver
+    );
+}
+
+#[test]
+fn table_keeps_the_best_runs_in_order() {
+    let mut high_scores = HighScores::default();
+    assert!(!high_scores.qualifies(0));
+    for score in [30, 10, 20] {
+        high_scores.insert(entry(score));
+    }
+    assert_eq!(high_scores.insert(entry(20)), Some(2));
+    let scores = |high_scores: &HighScores| {
+        high_scores
+            .entries
+            .iter()
+            .map(|entry| entry.score)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(scores(&high_scores), vec![30, 20, 20, 10]);
+
+    for score in 100..110 {
+        high_scores.insert(entry(score));
+    }
+    assert_eq!(high_scores.entries.len(), HIGH_SCORE_COUNT);
+    assert!(!high_scores.qualifies(100));
+    assert_eq!(high_scores.insert(entry(50)), None);
+    assert_eq!(high_scores.insert(entry(105)), Some(5));
+    assert_eq!(scores(&high_scores)[0], 109);
+    assert_eq!(*scores(&high_scores).last().unwrap(), 101);
+}
+
+#[test]
+fn missing_and_corrupt_files_start_an_empty_table() {
+    let file = temp_file("corrupt");
+    assert_eq!(file.load(), HighScores::default());
+
+    let mut high_scores = HighScores::default();
+    high_scores.insert(entry(10));
+    high_scores.save(&file.0).unwrap();
+    assert_eq!(file.load(), high_scores);
+
+    std::fs::write(&file.0, "(version: 1, entries: [(score: ").unwrap();
+    assert_eq!(file.load(), HighScores::default());
+    assert!(!file.0.exists());
+    let corrupt = file.0.with_extension("ron.corrupt");
+    assert!(corrupt.exists());
+    std::fs::remove_file(corrupt).unwrap();
+}
+
+#[test]
+fn dates_are_shown_in_utc() {
+    let date = |date| HighScore { date, ..entry(0) }.date_string();
+    assert_eq!(date(0), "1970-01-01");
+    assert_eq!(date(951_782_400), "2000-02-29");
+    assert_eq!(date(1_791_201_599), "2026-10-05");
+}
+
+#[test]
+fn records_are_saved_with_the_initials() {
+    let file = temp_file("record");
+    let mut app = game_app();
+    app.insert_resource(file.clone());
+    lose_game(&mut app);
+
+    let pending = app.world.resource::<PendingHighScore>();
+    let record = pending.entry.clone().unwrap();
+    assert!(record.score > 0);
+    assert_eq!(record.highest_tier, 1);
+    assert!(record.duration > 0.0);
+
+    app.world.send_event(SubmitHighScoreEvent {
+        initials: "xyzw".to_string(),
+    });
+    app.update();
+    let pending = app.world.resource::<PendingHighScore>();
+    assert!(pending.entry.is_none());
+    assert_eq!(pending.last_rank, Some(0));
+    let saved = file.load();
+    std::fs::remove_file(&file.0).unwrap();
+    assert_eq!(&saved, app.world.resource::<HighScores>());
+    assert_eq!(
+        saved.entries,
+        vec![HighScore {
+            initials: "XYZ".to_string(),
+            ..record
+        }]
+    );
+}
+
+#[test]
+fn restarting_keeps_records_without_initials() {
+    let mut app = game_app();
+    lose_game(&mut app);
+    assert!(app.world.resource::<PendingHighScore>().entry.is_some());
+
+    app.world.send_event(RestartEvent);
+    app.update();
+    let high_scores = app.world.resource::<HighScores>();
+    assert_eq!(high_scores.entries.len(), 1);
+    assert_eq!(high_scores.entries[0].initials, "???");
+    assert!(app.world.resource::<PendingHighScore>().entry.is_none());
+}
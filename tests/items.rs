@@ -0,0 +1,194 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use bevy::{input::InputPlugin, prelude::*, time::TimeUpdateStrategy};
+
+use combobox::{
+    add_game_plugins,
+    items::{ItemTiers, ItemTiersError, ItemTiersHandle, ItemsResources},
+    physics::{Ball, InterpolatedTransform, PhysicsPlugin, Sleeping},
+    platform::SpawnItemEvent,
+    scene::ScenePlugin,
+    Score,
+};
+use common::{run_for, start, TICK_RATE};
+
+/// Headless game with an `AssetServer`, waiting until the tiers file loaded.
+fn asset_app() -> App {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, AssetPlugin::default(), InputPlugin));
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+        1.0 / TICK_RATE,
+    )));
+    add_game_plugins(
+        &mut app,
+        PhysicsPlugin {
+            tick_rate: TICK_RATE,
+            ..default()
+        },
+        ScenePlugin::default(),
+    );
+    let mut app = start(app);
+
+    let loading = Instant::now();
+    loop {
+        let handle = &app.world.resource::<ItemTiersHandle>().0;
+        if app
+            .world
+            .resource::<AssetServer>()
+            .is_loaded_with_dependencies(handle)
+        {
+            break;
+        }
+        assert!(
+            loading.elapsed() < Duration::from_secs(10),
+            "tiers not loaded"
+        );
+        std::thread::sleep(Duration::from_millis(1));
+        app.update();
+    }
+    // Let the tiers be applied.
+    app.update();
+    app
+}
+
+fn tiers_with_radii(radii: &[f32]) -> String {
+    let tiers = radii
+        .iter()
+        .map(|radius| {
+            format!(
+                "(radius: {radius}, bounciness: 0.2, friction: 0.5, color: \"ffffff\", \
+                 score: 2, spawn_weight: 1.0)"
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("(tiers: [{tiers}])")
+}
+
+fn replace_tiers(app: &mut App, tiers: ItemTiers) {
+    let handle = app.world.resource::<ItemTiersHandle>().0.clone();
+    *app.world
+        .resource_mut::<Assets<ItemTiers>>()
+        .get_mut(&handle)
+        .unwrap() = tiers;
+    // Asset events are sent at the end of the frame and handled in the next.
+    app.update();
+    app.update();
+}
+
+#[test]
+fn bundled_tiers_are_valid() {
+    let tiers = ItemTiers::from_ron(include_str!("../assets/items.ron")).unwrap();
+    assert_eq!(tiers.tiers.len(), 5);
+    assert!(tiers.final_item.is_some());
+    assert!(tiers
+        .tiers
+        .windows(2)
+        .all(|pair| pair[0].radius < pair[1].radius));
+}
+
+#[test]
+fn invalid_tiers_are_rejected() {
+    let invalid = |ron: &str| match ItemTiers::from_ron(ron) {
+        Err(ItemTiersError::Invalid(_)) => {}
+        result => panic!("{ron} gave {result:?}"),
+    };
+
+    invalid("(tiers: [])");
+    invalid(&tiers_with_radii(&[5.0, -1.0]));
+    invalid(&tiers_with_radii(&[5.0]).replace("ffffff", "not a colour"));
+    invalid(&tiers_with_radii(&[5.0]).replace("spawn_weight: 1.0", "spawn_weight: 0.0"));
+    assert!(matches!(
+        ItemTiers::from_ron("(tiers: [(radius: 5.0)])"),
+        Err(ItemTiersError::Ron(_))
+    ));
+}
+
+#[test]
+fn tiers_are_loaded_from_the_asset_file() {
+    let app = asset_app();
+
+    let items = app.world.resource::<ItemsResources>();
+    let file = ItemTiers::from_ron(include_str!("../assets/items.ron")).unwrap();
+    assert_eq!(items.tiers.len(), file.tiers.len());
+    for (item, tier) in items.tiers.iter().zip(&file.tiers) {
+        assert_eq!(item.radius, tier.radius);
+        assert_eq!(item.score, tier.score);
+    }
+}
+
+#[test]
+fn reloaded_tiers_update_existing_items() {
+    let mut app = asset_app();
+    app.world.send_event(SpawnItemEvent {
+        item_type: 1,
+        position: Vec3::new(50.0, 0.0, 50.0),
+        ..default()
+    });
+    app.update();
+
+    let tiers = ItemTiers::from_ron(&tiers_with_radii(&[4.0, 6.0, 9.0])).unwrap();
+    replace_tiers(&mut app, tiers);
+
+    assert_eq!(app.world.resource::<ItemsResources>().tiers.len(), 3);
+    let ball = *app.world.query::<&Ball>().single(&app.world);
+    assert_eq!(ball.radius, 6.0);
+    assert_eq!(ball.bounciness, 0.2);
+}
+
+#[test]
+fn reloaded_tiers_wake_resized_items() {
+    let mut app = asset_app();
+    app.world.send_event(SpawnItemEvent {
+        item_type: 0,
+        position: Vec3::new(50.0, 0.0, 10.0),
+        ..default()
+    });
+    run_for(&mut app, 3.0);
+    let (entity, _) = app.world.query::<(Entity, &Sleeping)>().single(&app.world);
+    let bottom = |app: &mut App| {
+        let (ball, transform) = app
+            .world
+            .query::<(&Ball, &InterpolatedTransform)>()
+            .single(&app.world);
+        transform.current.z - ball.radius
+    };
+    let floor = bottom(&mut app);
+
+    // Sunk into the floor by the new radius.
+    let tiers = ItemTiers::from_ron(&tiers_with_radii(&[12.0, 16.0])).unwrap();
+    replace_tiers(&mut app, tiers);
+    assert!(app.world.get::<Sleeping>(entity).is_none());
+    run_for(&mut app, 1.0);
+    assert!((bottom(&mut app) - floor).abs() < 0.1);
+}
+
+#[test]
+fn any_number_of_tiers_merge_up_to_the_top() {
+    let mut app = asset_app();
+    let radii = (0..8).map(|i| 3.0 + i as f32).collect::<Vec<_>>();
+    let tiers = ItemTiers::from_ron(&tiers_with_radii(&radii)).unwrap();
+    replace_tiers(&mut app, tiers);
+
+    for x in [42.0, 58.0] {
+        app.world.send_event(SpawnItemEvent {
+            item_type: 6,
+            position: Vec3::new(x, 0.0, 20.0),
+            ..default()
+        });
+    }
+    run_for(&mut app, 1.0);
+
+    let balls = app
+        .world
+        .query::<&Ball>()
+        .iter(&app.world)
+        .copied()
+        .collect::<Vec<_>>();
+    assert_eq!(balls.len(), 1);
+    assert_eq!(balls[0].ball_type, 7);
+    assert_eq!(balls[0].radius, 10.0);
+    assert_eq!(app.world.resource::<Score>().score, 2);
+}
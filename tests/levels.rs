@@ -0,0 +1,218 @@
+mod common;
+
+use std::{f32::consts::PI, path::Path};
+
+use bevy::prelude::*;
+
+use combobox::{
+    game_over::DangerLine,
+    headless_app_with,
+    physics::{Ball, Collider, InterpolatedTransform, PhysicsPlugin},
+    platform::{Platform, PlatformBounds, SpawnItemEvent},
+    scene::{ArenaBounds, Level, LevelError, ScenePlugin},
+};
+use common::{run_for, TICK_RATE};
+
+fn bundled_levels() -> Vec<Level> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/levels");
+    let mut paths = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| Level::load(path).unwrap_or_else(|e| panic!("{path:?}: {e}")))
+        .collect()
+}
+
+fn level_app(level: Level) -> App {
+    let mut app = headless_app_with(
+        PhysicsPlugin {
+            tick_rate: TICK_RATE,
+            ..default()
+        },
+        ScenePlugin { level },
+    );
+    app.update();
+    app
+}
+
+fn balls(app: &mut App) -> Vec<(Ball, Vec3)> {
+    app.world
+        .query::<(&Ball, &InterpolatedTransform)>()
+        .iter(&app.world)
+        .map(|(ball, transform)| (*ball, transform.current))
+        .collect()
+}
+
+#[test]
+fn bundled_levels_are_valid() {
+    let levels = bundled_levels();
+    assert!(levels.len() >= 3);
+    assert!(levels.iter().any(|level| level.name == "Classic"));
+}
+
+#[test]
+fn classic_level_is_the_original_box() {
+    let level = Level::default();
+    let wall = Collider::Rectangle {
+        width: 100.0,
+        height: 5.0,
+    };
+    let side = Quat::from_rotation_y(-PI / 2.0);
+    let expected = [
+        (
+            Transform::from_xyz(0.0, 0.0, 50.0).with_rotation(side),
+            &wall,
+        ),
+        (
+            Transform::from_xyz(100.0, 0.0, 50.0).with_rotation(side),
+            &wall,
+        ),
+        (Transform::from_xyz(50.0, 0.0, 0.0), &wall),
+    ];
+
+    let colliders = level.colliders();
+    assert_eq!(colliders.len(), expected.len());
+    for ((transform, collider), (expected_transform, expected_collider)) in
+        colliders.iter().zip(expected)
+    {
+        assert_eq!(*transform, expected_transform);
+        assert_eq!(collider, expected_collider);
+    }
+}
+
+#[test]
+fn dropped_items_stay_inside_every_bundled_level() {
+    for level in bundled_levels() {
+        let name = level.name.clone();
+        let (width, height) = (level.width, level.height);
+        let bounds = level.platform_bounds();
+        let mut app = level_app(level);
+
+        for i in 0..12 {
+            let x = bounds.min_x + (bounds.max_x - bounds.min_x) * (i as f32 + 0.5) / 12.0;
+            let x = x.clamp(10.0, width - 10.0);
+            app.world.send_event(SpawnItemEvent {
+                item_type: (i % 2) as u8,
+                position: Vec3::new(x, 0.0, bounds.height - 1.0),
+                ..default()
+            });
+            run_for(&mut app, 0.3);
+        }
+        run_for(&mut app, 3.0);
+
+        for (ball, position) in balls(&mut app) {
+            assert!(
+                position.x > 2.5 + ball.radius - 0.5
+                    && position.x < width - 2.5 - ball.radius + 0.5,
+                "{name}: {position}"
+            );
+            assert!(position.z > 2.5 + ball.radius - 0.5, "{name}: {position}");
+            assert!(position.z < height, "{name}: {position}");
+        }
+    }
+}
+
+#[test]
+fn level_sets_platform_and_danger_line() {
+    let level = Level::from_ron(
+        "(name: \"Test\", width: 160.0, height: 80.0, \
+          platform: Some((height: 80.0, min_x: 20.0, max_x: 140.0)), danger_line: Some(60.0))",
+    )
+    .unwrap();
+    let mut app = level_app(level);
+
+    assert_eq!(app.world.resource::<DangerLine>().height, 60.0);
+    let bounds = *app.world.resource::<PlatformBounds>();
+    assert_eq!(
+        (bounds.min_x, bounds.max_x, bounds.height),
+        (20.0, 140.0, 80.0)
+    );
+    let platform = app
+        .world
+        .query_filtered::<&Transform, With<Platform>>()
+        .single(&app.world)
+        .translation;
+    assert_eq!(platform, Vec3::new(80.0, 0.0, 80.0));
+
+    app.world
+        .resource_mut::<ButtonInput<KeyCode>>()
+        .press(KeyCode::KeyD);
+    run_for(&mut app, 3.0);
+    let platform = app
+        .world
+        .query_filtered::<&Transform, With<Platform>>()
+        .single(&app.world)
+        .translation;
+    assert_eq!(platform.x, 140.0);
+}
+
+#[test]
+fn escaped_items_are_despawned() {
+    let level = Level::from_ron("(name: \"Test\", width: 100.0, height: 100.0)").unwrap();
+    let mut app = level_app(level);
+    let arena = *app.world.resource::<ArenaBounds>();
+    assert!(arena.min.x < 0.0 && arena.max.x > 100.0);
+
+    for x in [50.0, arena.max.x + 20.0] {
+        app.world.send_event(SpawnItemEvent {
+            item_type: 0,
+            position: Vec3::new(x, 0.0, 50.0),
+            ..default()
+        });
+    }
+    run_for(&mut app, 0.5);
+
+    let balls = balls(&mut app);
+    assert_eq!(balls.len(), 1);
+    assert_eq!(balls[0].1.x, 50.0);
+}
+
+#[test]
+fn level_starts_with_its_balls() {
+    let level = Level::from_ron(
+        "(name: \"Test\", width: 100.0, height: 100.0, balls: [\
+          (item_type: 1, position: (30.0, 20.0)), (item_type: 3, position: (70.0, 30.0))])",
+    )
+    .unwrap();
+    let mut app = level_app(level);
+    run_for(&mut app, 1.0);
+
+    let mut types = balls(&mut app)
+        .iter()
+        .map(|(ball, _)| ball.ball_type)
+        .collect::<Vec<_>>();
+    types.sort();
+    assert_eq!(types, vec![1, 3]);
+}
+
+#[test]
+fn invalid_levels_are_rejected() {
+    let invalid = |ron: &str| match Level::from_ron(ron) {
+        Err(LevelError::Invalid(_)) => {}
+        result => panic!("{ron} gave {result:?}"),
+    };
+
+    invalid("(name: \"\", width: 0.0, height: 100.0)");
+    invalid(
+        "(name: \"\", width: 100.0, height: 100.0, \
+          platform: Some((height: 100.0, min_x: 80.0, max_x: 20.0)))",
+    );
+    // Clockwise
+    invalid(
+        "(name: \"\", width: 100.0, height: 100.0, colliders: [(position: (50.0, 50.0), \
+          shape: Polygon(points: [(0.0, 0.0), (0.0, 10.0), (10.0, 0.0)]))])",
+    );
+    // Concave
+    invalid(
+        "(name: \"\", width: 100.0, height: 100.0, colliders: [(position: (50.0, 50.0), \
+          shape: Polygon(points: [(0.0, 0.0), (10.0, 0.0), (5.0, 2.0), (10.0, 10.0), \
+          (0.0, 10.0)]))])",
+    );
+    assert!(matches!(
+        Level::from_ron("(width: 100.0)"),
+        Err(LevelError::Ron(_))
+    ));
+}
@@ -0,0 +1,369 @@
+mod common;
+
+use bevy::{ecs::event::ManualEventReader, prelude::*};
+
+use combobox::{
+    headless_app_with,
+    items::{ItemResource, ItemsResources},
+    physics::{Ball, CollisionEvent, InterpolatedTransform, PhysicsPlugin, TopTierMerge},
+    platform::SpawnItemEvent,
+    scene::ScenePlugin,
+    score::{ScoreEvent, COMBO_WINDOW},
+    Score,
+};
+use common::{start, TICK_RATE};
+
+/// Headless game with the scene spawned and collision and score events
+/// recorded.
+struct TestGame {
+    app: App,
+    collision_reader: ManualEventReader<CollisionEvent>,
+    collisions: Vec<(Entity, Entity)>,
+    score_reader: ManualEventReader<ScoreEvent>,
+    score_events: Vec<ScoreEvent>,
+}
+
+impl TestGame {
+    fn new() -> Self {
+        Self::with_top_tier_merge(TopTierMerge::default())
+    }
+
+    fn with_top_tier_merge(top_tier_merge: TopTierMerge) -> Self {
+        let app = start(headless_app_with(
+            PhysicsPlugin {
+                tick_rate: TICK_RATE,
+                top_tier_merge,
+                ..default()
+            },
+            ScenePlugin::default(),
+        ));
+        let collision_reader = app.world.resource::<Events<CollisionEvent>>().get_reader();
+        let score_reader = app.world.resource::<Events<ScoreEvent>>().get_reader();
+        Self {
+            app,
+            collision_reader,
+            collisions: vec![],
+            score_reader,
+            score_events: vec![],
+        }
+    }
+
+    fn spawn(&mut self, item_type: u8, x: f32, z: f32) {
+        self.app.world.send_event(SpawnItemEvent {
+            item_type,
+            position: Vec3::new(x, 0.0, z),
+            ..default()
+        });
+    }
+
+    fn step(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.app.update();
+            let events = self.app.world.resource::<Events<CollisionEvent>>();
+            self.collisions.extend(
+                self.collision_reader
+                    .read(events)
+                    .map(|event| (event.entity1, event.entity2)),
+            );
+            let events = self.app.world.resource::<Events<ScoreEvent>>();
+            self.score_events
+                .extend(self.score_reader.read(events).copied());
+        }
+    }
+
+    fn run_for(&mut self, seconds: f64) {
+        self.step((seconds * TICK_RATE) as u32);
+    }
+
+    /// All balls with their positions, ordered by type and then by x.
+    fn balls(&mut self) -> Vec<(Ball, Vec3)> {
+        let mut balls = self
+            .app
+            .world
+            .query::<(&Ball, &InterpolatedTransform)>()
+            .iter(&self.app.world)
+            .map(|(ball, transform)| (*ball, transform.current))
+            .collect::<Vec<_>>();
+        balls.sort_by(|(a, a_position), (b, b_position)| {
+            a.ball_type
+                .cmp(&b.ball_type)
+                .then(a_position.x.total_cmp(&b_position.x))
+        });
+        balls
+    }
+
+    fn types(&mut self) -> Vec<u8> {
+        self.balls()
+            .iter()
+            .map(|(ball, _)| ball.ball_type)
+            .collect()
+    }
+
+    fn ball_entities(&mut self) -> Vec<Entity> {
+        self.app
+            .world
+            .query_filtered::<Entity, With<Ball>>()
+            .iter(&self.app.world)
+            .collect()
+    }
+
+    fn score(&self) -> u32 {
+        self.app.world.resource::<Score>().score
+    }
+
+    fn items(&self) -> &ItemsResources {
+        self.app.world.resource::<ItemsResources>()
+    }
+
+    fn item(&self, item_type: u8) -> &ItemResource {
+        self.items().get(item_type).unwrap()
+    }
+
+    fn top_tier(&self) -> u8 {
+        self.items().tiers.len() as u8 - 1
+    }
+}
+
+#[test]
+fn equal_items_merge_into_the_next_type() {
+    let mut game = TestGame::new();
+    game.spawn(0, 46.0, 20.0);
+    game.spawn(0, 54.0, 20.0);
+    game.step(2);
+
+    let balls = game.balls();
+    assert_eq!(balls.len(), 1);
+    let (ball, position) = balls[0];
+    assert_eq!(ball.ball_type, 1);
+    assert!((position.x - 50.0).abs() < 1.0, "{position}");
+    assert_eq!(game.score(), game.item(1).score);
+}
+
+#[test]
+fn merged_item_uses_the_next_tier_radius() {
+    let mut game = TestGame::new();
+    game.spawn(0, 46.0, 20.0);
+    game.spawn(0, 54.0, 20.0);
+    game.run_for(2.0);
+
+    let (ball, position) = game.balls()[0];
+    let radius = game.item(1).radius;
+    assert_eq!(ball.radius, radius);
+    assert!((position.z - (2.5 + radius)).abs() < 0.5, "{position}");
+}
+
+#[test]
+fn different_items_collide_without_merging() {
+    let mut game = TestGame::new();
+    game.spawn(0, 44.0, 20.0);
+    game.spawn(1, 54.0, 20.0);
+    game.step(1);
+
+    let entities = game.ball_entities();
+    assert_eq!(entities.len(), 2);
+    game.step(1);
+
+    assert!(game.collisions.iter().any(|(entity1, entity2)| {
+        entities.contains(entity1) && entities.contains(entity2) && entity1 != entity2
+    }));
+    game.run_for(2.0);
+    assert_eq!(game.types(), vec![0, 1]);
+    assert_eq!(game.score(), 0);
+}
+
+#[test]
+fn separated_equal_items_do_not_merge() {
+    let mut game = TestGame::new();
+    game.spawn(0, 20.0, 10.0);
+    game.spawn(0, 80.0, 10.0);
+    game.run_for(2.0);
+
+    assert_eq!(game.balls().len(), 2);
+    assert_eq!(game.score(), 0);
+}
+
+#[test]
+fn items_resting_on_the_floor_report_collisions() {
+    let mut game = TestGame::new();
+    game.spawn(1, 50.0, 40.0);
+    game.run_for(1.0);
+
+    let ball = game.ball_entities()[0];
+    assert!(game
+        .collisions
+        .iter()
+        .any(|(entity1, entity2)| *entity1 == ball && *entity2 != ball));
+}
+
+#[test]
+fn items_stay_inside_the_walls() {
+    let mut game = TestGame::new();
+    // A mix of types dropped from the platform height, so the box fills up
+    // while some of the items merge on the way.
+    for i in 0..30u32 {
+        let x = 10.0 + (i * 37 % 80) as f32;
+        game.spawn((i % 3) as u8, x, 95.0);
+        game.run_for(0.25);
+    }
+    game.run_for(3.0);
+
+    for (Ball { radius, .. }, position) in game.balls() {
+        assert!(
+            position.x > 2.5 + radius - 0.5 && position.x < 97.5 - radius + 0.5,
+            "{position}"
+        );
+        assert!(position.z > 2.5 + radius - 0.5, "{position}");
+    }
+}
+
+#[test]
+fn triple_contact_merges_only_one_pair() {
+    let mut game = TestGame::new();
+    // Three touching items of the same type, each overlapping both others.
+    game.spawn(0, 46.0, 20.0);
+    game.spawn(0, 54.0, 20.0);
+    game.spawn(0, 50.0, 26.0);
+    game.step(2);
+
+    assert_eq!(game.types(), vec![0, 1]);
+    assert_eq!(game.score(), game.item(1).score);
+}
+
+#[test]
+fn row_of_contacts_merges_each_item_at_most_once() {
+    let mut game = TestGame::new();
+    for x in [40.0, 48.0, 56.0, 64.0] {
+        game.spawn(0, x, 20.0);
+    }
+    game.step(2);
+
+    let types = game.types();
+    let merged = types.iter().filter(|t| **t == 1).count() as u32;
+    let unmerged = types.iter().filter(|t| **t == 0).count() as u32;
+    assert_eq!(types.len() as u32, merged + unmerged);
+    assert!(merged >= 1);
+    assert_eq!(unmerged + 2 * merged, 4);
+    assert_eq!(game.score(), merged * game.item(1).score);
+}
+
+#[test]
+fn merges_chain_into_neighbouring_items() {
+    let mut game = TestGame::new();
+    let small = game.item(0).radius;
+    game.spawn(1, 50.0, 2.5 + game.item(1).radius);
+    game.run_for(1.0);
+
+    // The merged item appears overlapping the resting one of its new type.
+    game.spawn(0, 50.0 - small + 1.0, 25.0);
+    game.spawn(0, 50.0 + small - 1.0, 25.0);
+    game.step(4);
+
+    assert_eq!(game.types(), vec![2]);
+    // The second merge continues the chain of the first one.
+    assert_eq!(game.score(), game.item(1).score + 2 * game.item(2).score);
+}
+
+#[test]
+fn merges_report_their_points_tier_and_combo() {
+    let mut game = TestGame::new();
+    let small = game.item(0).radius;
+    game.spawn(1, 50.0, 2.5 + game.item(1).radius);
+    game.run_for(1.0);
+    game.spawn(0, 50.0 - small + 1.0, 25.0);
+    game.spawn(0, 50.0 + small - 1.0, 25.0);
+    game.step(4);
+
+    let events = game
+        .score_events
+        .iter()
+        .map(|event| (event.points, event.tier, event.combo))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        events,
+        vec![(game.item(1).score, 1, 1), (2 * game.item(2).score, 2, 2)]
+    );
+    let first = game.score_events[0].position;
+    assert!((first.x - 50.0).abs() < 1.0 && first.z > 20.0, "{first}");
+    let total = game
+        .score_events
+        .iter()
+        .map(|event| event.points)
+        .sum::<u32>();
+    assert_eq!(game.score(), total);
+}
+
+#[test]
+fn combos_end_after_the_combo_window() {
+    let mut game = TestGame::new();
+    game.spawn(0, 30.0, 10.0);
+    game.spawn(0, 38.0, 10.0);
+    game.run_for(COMBO_WINDOW as f64 + 0.5);
+
+    // Overlaps the item created by the first merge once the window is over.
+    let (_, merged) = game.balls()[0];
+    game.spawn(1, merged.x + 2.0 * game.item(1).radius - 1.0, merged.z);
+    game.step(4);
+
+    let combos = game
+        .score_events
+        .iter()
+        .map(|event| event.combo)
+        .collect::<Vec<_>>();
+    assert_eq!(combos, vec![1, 1]);
+    assert_eq!(game.types(), vec![2]);
+    assert_eq!(game.score(), game.item(1).score + game.item(2).score);
+}
+
+/// Two touching top tier items, run until they merged or settled.
+fn top_tier_pair(top_tier_merge: TopTierMerge) -> TestGame {
+    let mut game = TestGame::with_top_tier_merge(top_tier_merge);
+    let top = game.top_tier();
+    game.spawn(top, 35.0, 30.0);
+    game.spawn(top, 65.0, 30.0);
+    game.run_for(2.0);
+    game
+}
+
+#[test]
+fn top_tier_items_vanish_with_a_bonus() {
+    let mut game = top_tier_pair(TopTierMerge::Vanish { bonus: 250 });
+
+    assert!(game.balls().is_empty());
+    assert_eq!(game.score(), 250);
+}
+
+#[test]
+fn top_tier_items_merge_into_the_final_item() {
+    let mut game = top_tier_pair(TopTierMerge::FinalItem);
+
+    let balls = game.balls();
+    assert_eq!(balls.len(), 1);
+    let (ball, position) = balls[0];
+    let final_item = game.items().final_item_type();
+    let radius = game.item(final_item).radius;
+    assert_eq!(ball.ball_type, final_item);
+    assert_eq!(ball.radius, radius);
+    assert!((position.z - (2.5 + radius)).abs() < 0.5, "{position}");
+    assert_eq!(game.score(), game.item(final_item).score);
+}
+
+#[test]
+fn final_items_never_merge() {
+    let mut game = TestGame::with_top_tier_merge(TopTierMerge::FinalItem);
+    let final_item = game.items().final_item_type();
+    game.spawn(final_item, 25.0, 30.0);
+    game.spawn(final_item, 75.0, 30.0);
+    game.run_for(2.0);
+
+    assert_eq!(game.types(), vec![final_item, final_item]);
+    assert_eq!(game.score(), 0);
+}
+
+#[test]
+fn top_tier_items_can_refuse_to_merge() {
+    let mut game = top_tier_pair(TopTierMerge::Refuse);
+
+    let top = game.top_tier();
+    assert_eq!(game.types(), vec![top, top]);
+    assert_eq!(game.score(), 0);
+}
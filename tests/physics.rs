@@ -0,0 +1,538 @@
+mod common;
+
+use std::{f32::consts::PI, time::Duration};
+
+use bevy::{ecs::system::SystemState, prelude::*, time::TimeUpdateStrategy};
+
+use combobox::{
+    headless_app,
+    physics::{
+        AngularVelocity, Ball, CastHit, Collider, Dynamic, InterpolatedTransform, PhysicsDebug,
+        SleepTimer, Sleeping, SpatialQuery, Velocity,
+    },
+};
+use common::{run_for, start, TICK_RATE};
+
+fn physics_app(frame_time: Duration) -> App {
+    physics_app_with_tick_rate(TICK_RATE, frame_time)
+}
+
+fn physics_app_with_tick_rate(tick_rate: f64, frame_time: Duration) -> App {
+    let mut app = headless_app(tick_rate);
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(frame_time));
+    start(app)
+}
+
+fn spawn_collider(app: &mut App, transform: Transform, collider: Collider) {
+    app.world.spawn((transform, collider));
+}
+
+fn spawn_ball(app: &mut App, position: Vec3, radius: f32, ball_type: u8, velocity: Vec3) -> Entity {
+    app.world
+        .spawn((
+            Transform::from_translation(position),
+            Ball {
+                radius,
+                bounciness: 0.5,
+                friction: 0.5,
+                ball_type,
+            },
+            Dynamic,
+            InterpolatedTransform::new(position),
+            Velocity { velocity },
+            AngularVelocity::default(),
+            SleepTimer::default(),
+        ))
+        .id()
+}
+
+/// Ball positions ordered by entity, as falling asleep moves balls between
+/// archetypes and changes the query order.
+fn positions(app: &mut App) -> Vec<Vec3> {
+    let mut positions = app
+        .world
+        .query_filtered::<(Entity, &InterpolatedTransform), With<Ball>>()
+        .iter(&app.world)
+        .map(|(e, t)| (e, t.current))
+        .collect::<Vec<_>>();
+    positions.sort_by_key(|(e, _)| *e);
+    positions.into_iter().map(|(_, p)| p).collect()
+}
+
+fn run(frame_time: Duration, frames: u32) -> Vec<[u32; 3]> {
+    let mut app = physics_app(frame_time);
+    for i in 0..12 {
+        let position = Vec3::new(10.0 + i as f32 * 7.0, 0.0, 40.0 + (i % 3) as f32 * 15.0);
+        let ball_type = (i % 2) as u8;
+        let velocity = Vec3::new((i as f32 - 6.0) * 3.0, 0.0, 0.0);
+        spawn_ball(
+            &mut app,
+            position,
+            5.0 + ball_type as f32 * 3.0,
+            ball_type,
+            velocity,
+        );
+    }
+
+    for _ in 0..frames {
+        app.update();
+    }
+
+    assert_eq!(
+        app.world.resource::<Time<Fixed>>().elapsed(),
+        frame_time * frames
+    );
+    positions(&mut app)
+        .into_iter()
+        .map(|p| p.to_array().map(f32::to_bits))
+        .collect()
+}
+
+#[test]
+fn identical_runs_are_bit_identical() {
+    let frame_time = Duration::from_secs_f64(1.0 / TICK_RATE);
+    assert_eq!(run(frame_time, 300), run(frame_time, 300));
+}
+
+#[test]
+fn simulation_does_not_depend_on_frame_rate() {
+    let step = 1.0 / TICK_RATE;
+    let fast = run(Duration::from_secs_f64(step), 300);
+    let slow = run(Duration::from_secs_f64(step * 3.0), 100);
+    assert_eq!(fast, slow);
+}
+
+#[test]
+fn resting_pile_is_stable() {
+    const RADIUS: f32 = 5.0;
+
+    let mut app = physics_app(Duration::from_secs_f64(1.0 / TICK_RATE));
+    // Unique types so nothing merges.
+    for i in 0..30u8 {
+        let column = (i % 8) as f32;
+        let row = (i / 8) as f32;
+        let position = Vec3::new(12.0 + column * 11.0 + row, 0.0, 10.0 + row * 12.0);
+        spawn_ball(&mut app, position, RADIUS, 10 + i, Vec3::ZERO);
+    }
+
+    for _ in 0..(8.0 * TICK_RATE) as u32 {
+        app.update();
+    }
+    let settled = positions(&mut app);
+    assert_eq!(settled.len(), 30);
+
+    for (i, a) in settled.iter().enumerate() {
+        assert!(a.x > 2.5 + RADIUS - 0.5 && a.x < 97.5 - RADIUS + 0.5, "{a}");
+        assert!(a.z > 2.5 + RADIUS - 0.5, "{a}");
+        for b in settled[i + 1..].iter() {
+            assert!(a.distance(*b) > RADIUS * 2.0 - 0.5, "{a} overlaps {b}");
+        }
+    }
+
+    for _ in 0..TICK_RATE as u32 {
+        app.update();
+    }
+    for (before, after) in settled.iter().zip(positions(&mut app)) {
+        assert!(before.distance(after) < 0.1, "{before} moved to {after}");
+    }
+}
+
+#[test]
+fn sliding_ball_starts_rolling() {
+    const RADIUS: f32 = 5.0;
+
+    let mut app = physics_app(Duration::from_secs_f64(1.0 / TICK_RATE));
+    spawn_ball(
+        &mut app,
+        Vec3::new(20.0, 0.0, 2.5 + RADIUS),
+        RADIUS,
+        0,
+        Vec3::new(60.0, 0.0, 0.0),
+    );
+
+    for _ in 0..(0.25 * TICK_RATE) as u32 {
+        app.update();
+    }
+
+    let (velocity, angular_velocity, interpolated) = app
+        .world
+        .query::<(&Velocity, &AngularVelocity, &InterpolatedTransform)>()
+        .single(&app.world);
+    assert!(velocity.velocity.x > 0.0);
+    // Rolling to the right is a clockwise rotation in the XZ plane.
+    assert!(angular_velocity.velocity < 0.0);
+    let slip = velocity.velocity.x + angular_velocity.velocity * RADIUS;
+    assert!(slip.abs() < velocity.velocity.x * 0.1, "slip: {slip}");
+    assert_ne!(interpolated.current_rotation, Quat::IDENTITY);
+}
+
+#[test]
+fn resting_balls_fall_asleep() {
+    let mut app = physics_app(Duration::from_secs_f64(1.0 / TICK_RATE));
+    let ball = spawn_ball(&mut app, Vec3::new(50.0, 0.0, 20.0), 5.0, 0, Vec3::ZERO);
+
+    run_for(&mut app, 3.0);
+    assert!(app.world.get::<Sleeping>(ball).is_some());
+
+    let position = app
+        .world
+        .get::<InterpolatedTransform>(ball)
+        .unwrap()
+        .current;
+    run_for(&mut app, 1.0);
+    assert_eq!(
+        app.world
+            .get::<InterpolatedTransform>(ball)
+            .unwrap()
+            .current,
+        position
+    );
+}
+
+#[test]
+fn falling_ball_wakes_sleeping_ball() {
+    let mut app = physics_app(Duration::from_secs_f64(1.0 / TICK_RATE));
+    let sleeper = spawn_ball(&mut app, Vec3::new(50.0, 0.0, 7.5), 5.0, 0, Vec3::ZERO);
+    run_for(&mut app, 2.0);
+    assert!(app.world.get::<Sleeping>(sleeper).is_some());
+
+    spawn_ball(&mut app, Vec3::new(53.0, 0.0, 40.0), 8.0, 1, Vec3::ZERO);
+    let mut woken = false;
+    for _ in 0..TICK_RATE as u32 {
+        app.update();
+        woken |= app.world.get::<Sleeping>(sleeper).is_none();
+    }
+    assert!(woken);
+
+    run_for(&mut app, 6.0);
+    let sleeping = app
+        .world
+        .query_filtered::<(), With<Sleeping>>()
+        .iter(&app.world)
+        .count();
+    assert_eq!(sleeping, 2);
+}
+
+#[test]
+fn merge_wakes_balls_above() {
+    let mut app = physics_app(Duration::from_secs_f64(1.0 / TICK_RATE));
+    // A small ball resting in the valley between two big ones.
+    let left = spawn_ball(&mut app, Vec3::new(45.0, 0.0, 15.5), 13.0, 2, Vec3::ZERO);
+    let right = spawn_ball(&mut app, Vec3::new(71.0, 0.0, 15.5), 13.0, 3, Vec3::ZERO);
+    let top = spawn_ball(&mut app, Vec3::new(58.0, 0.0, 40.0), 8.0, 1, Vec3::ZERO);
+    run_for(&mut app, 4.0);
+    for ball in [left, right, top] {
+        assert!(app.world.get::<Sleeping>(ball).is_some());
+    }
+
+    // Rolling an equal ball into the left one merges them and takes away
+    // the support of `top`.
+    spawn_ball(
+        &mut app,
+        Vec3::new(17.0, 0.0, 15.5),
+        13.0,
+        2,
+        Vec3::new(80.0, 0.0, 0.0),
+    );
+    let mut woken = false;
+    for _ in 0..TICK_RATE as u32 {
+        app.update();
+        woken |= app.world.get::<Sleeping>(top).is_none();
+    }
+    assert!(app.world.get_entity(left).is_none());
+    assert!(woken);
+}
+
+/// Fires a ball at a wall with a physics step long enough to move it further
+/// than the wall is thick and checks it never gets past the inner side.
+#[test]
+fn debug_overlay_records_contacts_without_spawning() {
+    let mut app = physics_app(Duration::from_secs_f64(1.0 / TICK_RATE));
+    // Resting on the floor.
+    spawn_ball(&mut app, Vec3::new(50.0, 0.0, 7.5), 5.0, 0, Vec3::ZERO);
+    app.update();
+    assert!(app.world.resource::<PhysicsDebug>().contacts.is_empty());
+
+    let entities = app.world.entities().len();
+    app.world.resource_mut::<PhysicsDebug>().enabled = true;
+    for _ in 0..10 {
+        app.update();
+        let contacts = &app.world.resource::<PhysicsDebug>().contacts;
+        assert_eq!(contacts.len(), 1, "{contacts:?}");
+        // Pushing the ball up from the floor.
+        assert!(
+            contacts[0].normal.abs_diff_eq(Vec2::Y, 1e-4),
+            "{contacts:?}"
+        );
+    }
+    assert_eq!(app.world.entities().len(), entities);
+
+    app.world.resource_mut::<PhysicsDebug>().enabled = false;
+    app.update();
+    assert!(app.world.resource::<PhysicsDebug>().contacts.is_empty());
+}
+
+#[test]
+fn collider_outlines_follow_the_boundary() {
+    let capsule = Collider::Capsule {
+        half_length: 10.0,
+        radius: 2.0,
+    };
+    let outline = capsule.outline(32);
+    assert!(outline.len() >= 32);
+    for point in outline.iter() {
+        let on_segment = Vec2::new(point.x.clamp(-10.0, 10.0), 0.0);
+        assert!((point.distance(on_segment) - 2.0).abs() < 1e-4, "{point}");
+    }
+
+    let rectangle = Collider::Rectangle {
+        width: 4.0,
+        height: 2.0,
+    };
+    assert_eq!(
+        rectangle.outline(32),
+        vec![
+            Vec2::new(-2.0, -1.0),
+            Vec2::new(2.0, -1.0),
+            Vec2::new(2.0, 1.0),
+            Vec2::new(-2.0, 1.0)
+        ]
+    );
+}
+
+fn fire_at_wall(tick_rate: f64, position: Vec3, velocity: Vec3) {
+    const RADIUS: f32 = 5.0;
+
+    let mut app = physics_app_with_tick_rate(tick_rate, Duration::from_secs_f64(1.0 / tick_rate));
+    let ball = spawn_ball(&mut app, position, RADIUS, 0, velocity);
+    for _ in 0..(tick_rate * 2.0) as u32 {
+        app.update();
+        let position = app
+            .world
+            .get::<InterpolatedTransform>(ball)
+            .unwrap()
+            .current;
+        assert!(
+            2.5 + RADIUS - 0.5 < position.x && position.x < 97.5 - RADIUS + 0.5,
+            "{position}"
+        );
+        assert!(2.5 + RADIUS - 0.5 < position.z, "{position}");
+    }
+}
+
+#[test]
+fn fast_ball_does_not_tunnel_through_side_walls() {
+    for tick_rate in [4.0, 8.0, 16.0] {
+        fire_at_wall(
+            tick_rate,
+            Vec3::new(80.0, 0.0, 50.0),
+            Vec3::new(100.0, 0.0, 0.0),
+        );
+        fire_at_wall(
+            tick_rate,
+            Vec3::new(20.0, 0.0, 50.0),
+            Vec3::new(-100.0, 0.0, 0.0),
+        );
+    }
+}
+
+#[test]
+fn fast_ball_does_not_tunnel_through_floor() {
+    for tick_rate in [4.0, 8.0, 16.0] {
+        fire_at_wall(
+            tick_rate,
+            Vec3::new(50.0, 0.0, 30.0),
+            Vec3::new(0.0, 0.0, -100.0),
+        );
+    }
+}
+
+#[test]
+fn fast_ball_does_not_tunnel_through_corner() {
+    for tick_rate in [4.0, 8.0, 16.0] {
+        fire_at_wall(
+            tick_rate,
+            Vec3::new(70.0, 0.0, 30.0),
+            Vec3::new(100.0, 0.0, -100.0),
+        );
+    }
+}
+
+fn position(app: &App, ball: Entity) -> Vec3 {
+    app.world
+        .get::<InterpolatedTransform>(ball)
+        .unwrap()
+        .current
+}
+
+#[test]
+fn ball_rolls_down_slanted_plank() {
+    let mut app = physics_app(Duration::from_secs_f64(1.0 / TICK_RATE));
+    // Rotating around Y by a positive angle lowers the right end.
+    spawn_collider(
+        &mut app,
+        Transform::from_xyz(50.0, 0.0, 50.0).with_rotation(Quat::from_rotation_y(0.4)),
+        Collider::Rectangle {
+            width: 60.0,
+            height: 4.0,
+        },
+    );
+    let ball = spawn_ball(&mut app, Vec3::new(40.0, 0.0, 70.0), 5.0, 0, Vec3::ZERO);
+
+    let mut lowest_on_plank = f32::INFINITY;
+    for _ in 0..(2.0 * TICK_RATE) as u32 {
+        app.update();
+        let p = position(&app, ball);
+        if p.x < 75.0 {
+            // Plank surface under the ball plus the ball radius.
+            let surface = 50.0 - (p.x - 50.0) * 0.4f32.tan() + 2.0 / 0.4f32.cos();
+            lowest_on_plank = lowest_on_plank.min(p.z - surface);
+        }
+    }
+    assert!(position(&app, ball).x > 80.0);
+    assert!(lowest_on_plank > 5.0 - 0.5, "{lowest_on_plank}");
+}
+
+#[test]
+fn ball_bounces_off_peg() {
+    let mut app = physics_app(Duration::from_secs_f64(1.0 / TICK_RATE));
+    spawn_collider(
+        &mut app,
+        Transform::from_xyz(50.0, 0.0, 40.0),
+        Collider::Circle { radius: 3.0 },
+    );
+    let ball = spawn_ball(&mut app, Vec3::new(52.0, 0.0, 70.0), 5.0, 0, Vec3::ZERO);
+
+    for _ in 0..TICK_RATE as u32 {
+        app.update();
+        let p = position(&app, ball);
+        assert!(p.xz().distance(Vec2::new(50.0, 40.0)) > 8.0 - 0.5, "{p}");
+    }
+    assert!(position(&app, ball).x > 60.0);
+}
+
+#[test]
+fn ball_rests_on_line_segment() {
+    let mut app = physics_app(Duration::from_secs_f64(1.0 / TICK_RATE));
+    spawn_collider(
+        &mut app,
+        Transform::from_xyz(50.0, 0.0, 30.0),
+        Collider::Capsule {
+            half_length: 30.0,
+            radius: 0.0,
+        },
+    );
+    let ball = spawn_ball(&mut app, Vec3::new(50.0, 0.0, 60.0), 5.0, 0, Vec3::ZERO);
+
+    run_for(&mut app, 2.0);
+    let p = position(&app, ball);
+    assert!((p.z - 35.0).abs() < 0.5, "{p}");
+    assert!(app.world.get::<Sleeping>(ball).is_some());
+}
+
+#[test]
+fn ball_rolls_off_convex_polygon() {
+    let mut app = physics_app(Duration::from_secs_f64(1.0 / TICK_RATE));
+    spawn_collider(
+        &mut app,
+        Transform::from_xyz(50.0, 0.0, 20.0),
+        Collider::ConvexPolygon {
+            points: vec![
+                Vec2::new(-20.0, 0.0),
+                Vec2::new(20.0, 0.0),
+                Vec2::new(0.0, 15.0),
+            ],
+        },
+    );
+    let ball = spawn_ball(&mut app, Vec3::new(45.0, 0.0, 60.0), 5.0, 0, Vec3::ZERO);
+
+    run_for(&mut app, 2.0);
+    let p = position(&app, ball);
+    assert!(p.x < 30.0, "{p}");
+    assert!((p.z - 7.5).abs() < 0.5, "{p}");
+}
+
+/// Casts a circle straight down from `origin`, ignoring `excluded`.
+fn cast_down(app: &mut App, origin: Vec2, radius: f32, excluded: Option<Entity>) -> CastHit {
+    let mut state = SystemState::<SpatialQuery>::new(&mut app.world);
+    let spatial_query = state.get(&app.world);
+    spatial_query
+        .cast_circle(origin, radius, Vec2::NEG_Y, 200.0, |entity| {
+            Some(entity) != excluded
+        })
+        .unwrap()
+}
+
+#[test]
+fn ray_cast_hits_the_floor() {
+    let mut app = physics_app(Duration::from_secs_f64(1.0 / TICK_RATE));
+    let hit = cast_down(&mut app, Vec2::new(50.0, 80.0), 0.0, None);
+
+    assert!((hit.distance - 77.5).abs() < 1e-4, "{hit:?}");
+    assert!(hit.point.abs_diff_eq(Vec2::new(50.0, 2.5), 1e-4), "{hit:?}");
+    assert!(hit.normal.abs_diff_eq(Vec2::Y, 1e-4), "{hit:?}");
+    let floor = app.world.get::<Transform>(hit.entity).unwrap().translation;
+    assert_eq!(floor, Vec3::new(50.0, 0.0, 0.0));
+}
+
+#[test]
+fn circle_cast_stops_on_balls_and_skips_filtered_ones() {
+    let mut app = physics_app(Duration::from_secs_f64(1.0 / TICK_RATE));
+    let ball = spawn_ball(&mut app, Vec3::new(52.0, 0.0, 10.0), 5.0, 0, Vec3::ZERO);
+
+    let hit = cast_down(&mut app, Vec2::new(48.0, 80.0), 3.0, None);
+    assert_eq!(hit.entity, ball);
+    // Touching the ball 8 units away from its center, 4 of them along X.
+    let expected = 80.0 - 10.0 - (64.0f32 - 16.0).sqrt();
+    assert!((hit.distance - expected).abs() < 1e-3, "{hit:?}");
+    assert!((hit.point.distance(Vec2::new(52.0, 10.0)) - 5.0).abs() < 1e-3);
+
+    let hit = cast_down(&mut app, Vec2::new(48.0, 80.0), 3.0, Some(ball));
+    assert!((hit.distance - 74.5).abs() < 1e-4, "{hit:?}");
+}
+
+#[test]
+fn circle_cast_along_a_wall_reaches_the_floor() {
+    let mut app = physics_app(Duration::from_secs_f64(1.0 / TICK_RATE));
+    let hit = cast_down(&mut app, Vec2::new(7.6, 90.0), 5.0, None);
+    assert!((hit.distance - 82.5).abs() < 1e-4, "{hit:?}");
+}
+
+#[test]
+fn circle_cast_hits_rotated_and_rounded_colliders() {
+    let mut app = physics_app(Duration::from_secs_f64(1.0 / TICK_RATE));
+    spawn_collider(
+        &mut app,
+        Transform::from_xyz(30.0, 0.0, 40.0).with_rotation(Quat::from_rotation_y(PI / 4.0)),
+        Collider::Rectangle {
+            width: 20.0,
+            height: 20.0,
+        },
+    );
+    spawn_collider(
+        &mut app,
+        Transform::from_xyz(70.0, 0.0, 40.0),
+        Collider::Capsule {
+            half_length: 10.0,
+            radius: 2.0,
+        },
+    );
+
+    // Onto the top corner of the diamond.
+    let hit = cast_down(&mut app, Vec2::new(30.0, 90.0), 0.0, None);
+    let corner = 40.0 + 200.0f32.sqrt();
+    assert!((hit.distance - (90.0 - corner)).abs() < 1e-3, "{hit:?}");
+    // Onto its slanted side, with the normal of that side.
+    let hit = cast_down(&mut app, Vec2::new(35.0, 90.0), 0.0, None);
+    assert!(
+        (hit.distance - (90.0 - corner + 5.0)).abs() < 1e-3,
+        "{hit:?}"
+    );
+    let side_normal = Vec2::new(1.0, 1.0).normalize();
+    assert!(hit.normal.abs_diff_eq(side_normal, 1e-4), "{hit:?}");
+    // Onto the rounded end of the capsule.
+    let hit = cast_down(&mut app, Vec2::new(80.0, 90.0), 1.0, None);
+    assert!((hit.distance - (90.0 - 43.0)).abs() < 1e-3, "{hit:?}");
+    let hit = cast_down(&mut app, Vec2::new(85.0, 90.0), 0.0, None);
+    assert!((hit.distance - 87.5).abs() < 1e-3, "{hit:?}");
+}
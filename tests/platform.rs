@@ -0,0 +1,158 @@
+mod common;
+
+use bevy::{prelude::*, time::TimeUpdateStrategy};
+
+use std::time::Duration;
+
+use combobox::{
+    items::ItemsResources,
+    physics::{Ball, InterpolatedTransform},
+    platform::{
+        DropGuide, GhostItem, ItemQueue, Platform, PlatformBounds, QUEUE_LENGTH, SPAWN_OFFSET,
+    },
+};
+use common::{game_app, run_for, TICK_RATE};
+
+fn hold(app: &mut App, key: KeyCode, seconds: f64) {
+    app.world.resource_mut::<ButtonInput<KeyCode>>().press(key);
+    run_for(app, seconds);
+    app.world
+        .resource_mut::<ButtonInput<KeyCode>>()
+        .release(key);
+}
+
+fn platform(app: &mut App) -> (Vec3, u8) {
+    let (transform, platform) = app
+        .world
+        .query::<(&Transform, &Platform)>()
+        .single(&app.world);
+    (transform.translation, platform.next_item)
+}
+
+fn queue(app: &App) -> Vec<u8> {
+    app.world
+        .resource::<ItemQueue>()
+        .items
+        .iter()
+        .copied()
+        .collect()
+}
+
+#[test]
+fn queue_is_filled_from_the_start() {
+    let app = game_app();
+    assert_eq!(queue(&app).len(), QUEUE_LENGTH);
+}
+
+#[test]
+fn drops_take_the_next_item_from_the_queue() {
+    let mut app = game_app();
+    let (_, next_item) = platform(&mut app);
+    let queued = queue(&app);
+
+    // The spawn timer lets a single item drop after a second.
+    hold(&mut app, KeyCode::Space, 1.2);
+    run_for(&mut app, 0.1);
+
+    let balls = app
+        .world
+        .query::<&Ball>()
+        .iter(&app.world)
+        .map(|ball| ball.ball_type)
+        .collect::<Vec<_>>();
+    assert_eq!(balls, vec![next_item]);
+    assert_eq!(platform(&mut app).1, queued[0]);
+    let queue = queue(&app);
+    assert_eq!(queue.len(), QUEUE_LENGTH);
+    assert_eq!(queue[..QUEUE_LENGTH - 1], queued[1..]);
+}
+
+#[test]
+fn ghost_hangs_under_the_platform() {
+    let mut app = game_app();
+    hold(&mut app, KeyCode::KeyD, 0.2);
+    hold(&mut app, KeyCode::Space, 1.2);
+
+    let (translation, next_item) = platform(&mut app);
+    let (transform, ghost) = app
+        .world
+        .query::<(&Transform, &GhostItem)>()
+        .single(&app.world);
+    assert_eq!(transform.translation, translation + SPAWN_OFFSET);
+    assert_eq!(ghost.item_type, next_item);
+}
+
+#[test]
+fn platform_moves_between_physics_steps() {
+    let mut app = game_app();
+    // Two frames per physics step.
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+        0.5 / TICK_RATE,
+    )));
+    app.world
+        .resource_mut::<ButtonInput<KeyCode>>()
+        .press(KeyCode::KeyD);
+    // Until the first step, the platform is rendered where it started.
+    app.update();
+    app.update();
+    let mut x = platform(&mut app).0.x;
+    for _ in 0..16 {
+        app.update();
+        let (translation, _) = platform(&mut app);
+        assert!(translation.x > x, "{} after {x}", translation.x);
+        x = translation.x;
+        let (ghost, _) = app
+            .world
+            .query::<(&Transform, &GhostItem)>()
+            .single(&app.world);
+        assert_eq!(ghost.translation, translation + SPAWN_OFFSET);
+    }
+}
+
+#[test]
+fn drop_guide_lands_on_the_items_below() {
+    let mut app = game_app();
+    hold(&mut app, KeyCode::Space, 1.2);
+    run_for(&mut app, 2.0);
+
+    let (ball, below) = app
+        .world
+        .query::<(&Ball, &InterpolatedTransform)>()
+        .single(&app.world);
+    let (ball, below) = (*ball, below.current);
+    let (translation, next_item) = platform(&mut app);
+    assert_eq!(translation.x, below.x);
+    let radius = app
+        .world
+        .resource::<ItemsResources>()
+        .get(next_item)
+        .unwrap()
+        .radius;
+    let landing = app.world.resource::<DropGuide>().landing.unwrap();
+    assert_eq!(landing.x, translation.x);
+    assert!(
+        (landing.z - (below.z + ball.radius + radius)).abs() < 1e-3,
+        "{landing} above {below}"
+    );
+
+    app.world.resource_mut::<DropGuide>().enabled = false;
+    app.update();
+    assert!(app.world.resource::<DropGuide>().landing.is_none());
+}
+
+#[test]
+fn platform_stops_where_the_next_item_touches_the_wall() {
+    let mut app = game_app();
+    let (left, right) = app.world.resource::<PlatformBounds>().walls.unwrap();
+    assert!(left < right);
+
+    hold(&mut app, KeyCode::KeyA, 3.0);
+    let (translation, next_item) = platform(&mut app);
+    let radius = app
+        .world
+        .resource::<ItemsResources>()
+        .get(next_item)
+        .unwrap()
+        .radius;
+    assert_eq!(translation.x, left + radius);
+}
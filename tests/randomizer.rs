@@ -0,0 +1,92 @@
+mod common;
+
+use combobox::{
+    headless_app,
+    items::{ItemTiers, ItemsResources},
+    platform::{ItemQueue, Platform},
+    randomizer::{ItemRandomizer, Randomizer, BAG_COPIES},
+};
+use common::{start, TICK_RATE};
+
+const RANDOMIZERS: [Randomizer; 3] = [Randomizer::Uniform, Randomizer::Weighted, Randomizer::Bag];
+
+fn items() -> ItemsResources {
+    ItemsResources::new(&ItemTiers::default(), None, None, None)
+}
+
+fn sequence(randomizer: &mut ItemRandomizer, items: &ItemsResources, count: usize) -> Vec<u8> {
+    (0..count).map(|_| randomizer.next_item(items)).collect()
+}
+
+#[test]
+fn seed_determines_the_sequence() {
+    let items = items();
+    for kind in RANDOMIZERS {
+        let first = sequence(&mut ItemRandomizer::new(kind, 42), &items, 100);
+        let second = sequence(&mut ItemRandomizer::new(kind, 42), &items, 100);
+        let other = sequence(&mut ItemRandomizer::new(kind, 43), &items, 100);
+        assert_eq!(first, second, "{kind:?}");
+        assert_ne!(first, other, "{kind:?}");
+    }
+}
+
+#[test]
+fn reset_starts_the_sequence_over() {
+    let items = items();
+    let mut randomizer = ItemRandomizer::new(Randomizer::Bag, 3);
+    let first = sequence(&mut randomizer, &items, 10);
+    randomizer.reset();
+    assert_eq!(sequence(&mut randomizer, &items, 10), first);
+}
+
+#[test]
+fn only_droppable_tiers_are_picked() {
+    let items = items();
+    let droppable = items.droppable_tiers().collect::<Vec<_>>();
+    assert!(!droppable.is_empty() && droppable.len() < items.tiers.len());
+    for kind in RANDOMIZERS {
+        let sequence = sequence(&mut ItemRandomizer::new(kind, 7), &items, 200);
+        assert!(
+            sequence.iter().all(|item| droppable.contains(item)),
+            "{kind:?}"
+        );
+        for item in &droppable {
+            assert!(sequence.contains(item), "{kind:?} never picks {item}");
+        }
+    }
+}
+
+#[test]
+fn bags_hold_every_droppable_tier() {
+    let items = items();
+    let droppable = items.droppable_tiers().collect::<Vec<_>>();
+    let bag_size = droppable.len() * BAG_COPIES;
+    let sequence = sequence(
+        &mut ItemRandomizer::new(Randomizer::Bag, 11),
+        &items,
+        20 * bag_size,
+    );
+    for bag in sequence.chunks(bag_size) {
+        for item in &droppable {
+            let count = bag.iter().filter(|i| *i == item).count();
+            assert_eq!(count, BAG_COPIES, "{bag:?}");
+        }
+    }
+}
+
+#[test]
+fn platform_drops_the_randomizer_sequence() {
+    let mut app = headless_app(TICK_RATE);
+    app.insert_resource(ItemRandomizer::new(Randomizer::Uniform, 5));
+    let mut app = start(app);
+
+    let next_item = app.world.query::<&Platform>().single(&app.world).next_item;
+    let queue = app.world.resource::<ItemQueue>().items.iter().copied();
+    let dropped = std::iter::once(next_item).chain(queue).collect::<Vec<_>>();
+    let expected = sequence(
+        &mut ItemRandomizer::new(Randomizer::Uniform, 5),
+        &items(),
+        dropped.len(),
+    );
+    assert_eq!(dropped, expected);
+}
@@ -0,0 +1,161 @@
+mod common;
+
+use bevy::prelude::*;
+
+use combobox::{
+    headless_app,
+    physics::{Ball, InterpolatedTransform, TopTierMerge},
+    platform::{Platform, PlatformInput},
+    randomizer::{ItemRandomizer, Randomizer},
+    replay::{Replay, ReplayError, ReplayMode, ReplayPlugin, ReplayRecorder, REPLAY_VERSION},
+    scene::Level,
+    RestartEvent, Score,
+};
+use common::{start, TICK_RATE};
+
+fn replay_app(mode: ReplayMode) -> App {
+    let mut app = headless_app(TICK_RATE);
+    app.insert_resource(ItemRandomizer::new(Randomizer::Weighted, 1234));
+    app.add_plugins(ReplayPlugin { mode });
+    start(app)
+}
+
+/// Platform position, balls and score.
+fn session_state(app: &mut App) -> (f32, Vec<(u8, Vec3)>, u32) {
+    let platform = app
+        .world
+        .query_filtered::<&Transform, With<Platform>>()
+        .single(&app.world)
+        .translation
+        .x;
+    let mut balls = app
+        .world
+        .query::<(&Ball, &InterpolatedTransform)>()
+        .iter(&app.world)
+        .map(|(ball, transform)| (ball.ball_type, transform.current))
+        .collect::<Vec<_>>();
+    balls.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.x.total_cmp(&b.1.x)));
+    (platform, balls, app.world.resource::<Score>().score)
+}
+
+#[test]
+fn replays_reproduce_the_recorded_session() {
+    let path = std::env::temp_dir().join(format!("combobox-{}.replay.ron", std::process::id()));
+    let mut app = replay_app(ReplayMode::Record(path.clone()));
+    let script = [
+        (vec![KeyCode::KeyA], 0.4),
+        (vec![KeyCode::Space], 1.1),
+        (vec![KeyCode::KeyD, KeyCode::Space], 1.3),
+        (vec![], 0.5),
+        (vec![KeyCode::KeyA, KeyCode::Space], 2.2),
+        (vec![], 2.0),
+    ];
+    let mut updates = 0;
+    for (keys, seconds) in script {
+        let mut input = app.world.resource_mut::<ButtonInput<KeyCode>>();
+        for key in keys {
+            input.press(key);
+        }
+        for _ in 0..(seconds * TICK_RATE) as u32 {
+            app.update();
+            updates += 1;
+        }
+        app.world
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .release_all();
+    }
+    app.world.resource::<ReplayRecorder>().save().unwrap();
+    let recorded = session_state(&mut app);
+    assert!(recorded.1.len() >= 3);
+
+    let replay = Replay::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(replay.seed, 1234);
+    let mut app = replay_app(ReplayMode::Play(replay));
+    // The keyboard is ignored while a replay plays.
+    app.world
+        .resource_mut::<ButtonInput<KeyCode>>()
+        .press(KeyCode::KeyD);
+    for _ in 0..updates {
+        app.update();
+    }
+    assert_eq!(session_state(&mut app), recorded);
+}
+
+#[test]
+fn equal_inputs_are_merged() {
+    let mut replay = Replay::new(7, Randomizer::Bag, TICK_RATE);
+    let left = PlatformInput {
+        movement: -1.0,
+        drop: false,
+    };
+    for input in [left, left, PlatformInput::default(), left] {
+        replay.push(input);
+    }
+
+    assert_eq!(
+        replay.inputs,
+        vec![(2, left), (1, PlatformInput::default()), (1, left)]
+    );
+    assert_eq!(replay.len(), 4);
+    assert_eq!(Replay::from_ron(&replay.to_ron().unwrap()).unwrap(), replay);
+}
+
+#[test]
+fn other_versions_are_rejected() {
+    let mut replay = Replay::new(7, Randomizer::Bag, TICK_RATE);
+    replay.version = REPLAY_VERSION + 1;
+    assert!(matches!(
+        Replay::from_ron(&replay.to_ron().unwrap()),
+        Err(ReplayError::Invalid(_))
+    ));
+}
+
+#[test]
+fn replays_of_other_games_are_rejected() {
+    let path =
+        std::env::temp_dir().join(format!("combobox-{}.check.replay.ron", std::process::id()));
+    let app = replay_app(ReplayMode::Record(path));
+    let replay = app.world.resource::<ReplayRecorder>().replay.clone();
+    let classic = Level::default();
+    assert_eq!(replay.level, classic.name);
+    assert!(replay.check(&classic, TopTierMerge::default()).is_ok());
+
+    let wide = Level::from_ron(include_str!("../assets/levels/wide.level.ron")).unwrap();
+    assert!(matches!(
+        replay.check(&wide, TopTierMerge::default()),
+        Err(ReplayError::Mismatch(_))
+    ));
+    // Same name, edited layout.
+    let mut edited = classic.clone();
+    edited.width += 1.0;
+    assert!(matches!(
+        replay.check(&edited, TopTierMerge::default()),
+        Err(ReplayError::Mismatch(_))
+    ));
+    assert!(matches!(
+        replay.check(&classic, TopTierMerge::FinalItem),
+        Err(ReplayError::Mismatch(_))
+    ));
+}
+
+#[test]
+fn restarts_stop_the_recording() {
+    let path =
+        std::env::temp_dir().join(format!("combobox-{}.stop.replay.ron", std::process::id()));
+    let mut app = replay_app(ReplayMode::Record(path));
+    for _ in 0..10 {
+        app.update();
+    }
+    let steps = app.world.resource::<ReplayRecorder>().replay.len();
+    assert!(steps > 0);
+
+    app.world.send_event(RestartEvent);
+    for _ in 0..10 {
+        app.update();
+    }
+    let recorder = app.world.resource::<ReplayRecorder>();
+    assert!(recorder.stopped);
+    // Only the step run before the restart was handled.
+    assert!(recorder.replay.len() <= steps + 1);
+}
@@ -0,0 +1,203 @@
+mod common;
+
+use bevy::{app::AppExit, prelude::*};
+
+use combobox::{
+    headless_app,
+    platform::SpawnItemEvent,
+    randomizer::{ItemRandomizer, Randomizer},
+    save::{SaveError, SaveFile, SavedBall, Snapshot, MAX_SAVED_DRAWS, SAVE_VERSION},
+    scene::Level,
+    GameState,
+};
+use common::{run_for, start, TICK_RATE};
+
+fn game_app() -> App {
+    let mut app = headless_app(TICK_RATE);
+    app.insert_resource(ItemRandomizer::new(Randomizer::Bag, 99));
+    start(app)
+}
+
+/// A game with items still moving, after a few drops.
+fn game_in_progress() -> App {
+    let mut app = game_app();
+    app.world
+        .resource_mut::<ButtonInput<KeyCode>>()
+        .press(KeyCode::Space);
+    run_for(&mut app, 3.2);
+    app.world
+        .resource_mut::<ButtonInput<KeyCode>>()
+        .release(KeyCode::Space);
+    app.world.send_event(SpawnItemEvent {
+        item_type: 2,
+        position: Vec3::new(30.0, 0.0, 60.0),
+        ..default()
+    });
+    run_for(&mut app, 0.3);
+    app
+}
+
+fn capture(app: &mut App) -> Snapshot {
+    let mut snapshot = Snapshot::capture(&mut app.world);
+    snapshot
+        .balls
+        .sort_by(|a, b| a.translation.x.total_cmp(&b.translation.x));
+    snapshot
+}
+
+fn next_items(app: &mut App, count: usize) -> Vec<u8> {
+    app.world
+        .resource_scope(|world, mut randomizer: Mut<ItemRandomizer>| {
+            let items = world.resource();
+            (0..count).map(|_| randomizer.next_item(items)).collect()
+        })
+}
+
+#[test]
+fn snapshots_restore_the_game() {
+    let mut app = game_in_progress();
+    let saved = capture(&mut app);
+    assert!(saved.balls.len() >= 3);
+    assert!(saved.balls.iter().any(|ball| ball.velocity != Vec3::ZERO));
+    assert_eq!(Snapshot::from_ron(&saved.to_ron().unwrap()).unwrap(), saved);
+
+    // The level items of a new game are replaced.
+    let mut restored = headless_app(TICK_RATE);
+    restored.insert_resource(saved.clone());
+    // The clocks start with the second update, which spawns the items.
+    restored.update();
+    restored.update();
+    let snapshot = capture(&mut restored);
+    assert_eq!(snapshot.balls, saved.balls);
+    assert_eq!(
+        (
+            snapshot.platform_x,
+            snapshot.next_item,
+            &snapshot.queue,
+            snapshot.score,
+            snapshot.randomizer
+        ),
+        (
+            saved.platform_x,
+            saved.next_item,
+            &saved.queue,
+            saved.score,
+            saved.randomizer
+        )
+    );
+    // One physics step went by.
+    assert!((snapshot.spawn_timer - saved.spawn_timer - 1.0 / TICK_RATE as f32).abs() < 1e-4);
+    assert_eq!(next_items(&mut restored, 10), next_items(&mut app, 10));
+}
+
+#[test]
+fn saves_of_other_versions_are_rejected() {
+    let mut app = game_app();
+    let mut snapshot = capture(&mut app);
+    snapshot.version = SAVE_VERSION + 1;
+    assert!(matches!(
+        Snapshot::from_ron(&snapshot.to_ron().unwrap()),
+        Err(SaveError::Invalid(_))
+    ));
+}
+
+#[test]
+fn saves_with_implausible_draws_are_rejected() {
+    let mut app = game_app();
+    let mut snapshot = capture(&mut app);
+    snapshot.randomizer.draws = MAX_SAVED_DRAWS + 1;
+    assert!(matches!(
+        Snapshot::from_ron(&snapshot.to_ron().unwrap()),
+        Err(SaveError::Invalid(_))
+    ));
+    snapshot.randomizer.draws = u64::MAX;
+    let file =
+        SaveFile(std::env::temp_dir().join(format!("combobox-{}.draws.ron", std::process::id())));
+    snapshot.save(&file.0).unwrap();
+    let loaded = file.load(app.world.resource::<Level>());
+    file.remove().unwrap();
+    assert!(loaded.is_none());
+}
+
+#[test]
+fn saves_with_non_finite_values_are_rejected() {
+    let mut app = game_in_progress();
+    let snapshot = capture(&mut app);
+    assert!(!snapshot.balls.is_empty());
+    let corruptions: [fn(&mut Snapshot); 7] = [
+        |snapshot| snapshot.spawn_timer = -1.0,
+        |snapshot| snapshot.spawn_timer = f32::NAN,
+        |snapshot| snapshot.spawn_timer = f32::INFINITY,
+        |snapshot| snapshot.platform_x = f32::NAN,
+        |snapshot| snapshot.balls[0].translation.x = f32::INFINITY,
+        |snapshot| snapshot.balls[0].velocity.z = f32::NAN,
+        |snapshot| snapshot.balls[0].angular_velocity = f32::NEG_INFINITY,
+    ];
+    for corrupt in corruptions {
+        let mut corrupted = snapshot.clone();
+        corrupt(&mut corrupted);
+        let result = Snapshot::from_ron(&corrupted.to_ron().unwrap());
+        assert!(matches!(result, Err(SaveError::Invalid(_))), "{result:?}");
+    }
+}
+
+#[test]
+fn missing_ball_fields_get_defaults() {
+    let ball = ron::de::from_str::<SavedBall>("(item_type: 1, translation: (1.0, 0.0, 2.0))");
+    assert_eq!(
+        ball.unwrap(),
+        SavedBall {
+            item_type: 1,
+            translation: Vec3::new(1.0, 0.0, 2.0),
+            rotation: Quat::IDENTITY,
+            velocity: Vec3::ZERO,
+            angular_velocity: 0.0,
+        }
+    );
+}
+
+#[test]
+fn games_in_progress_are_saved_on_exit() {
+    let file =
+        SaveFile(std::env::temp_dir().join(format!("combobox-{}.save.ron", std::process::id())));
+    let mut app = game_in_progress();
+    app.insert_resource(file.clone());
+    app.world.send_event(AppExit);
+    app.update();
+    assert_eq!(
+        file.load(app.world.resource::<Level>())
+            .unwrap()
+            .balls
+            .len(),
+        capture(&mut app).balls.len()
+    );
+
+    // Lost games are not resumed.
+    app.world
+        .resource_mut::<NextState<GameState>>()
+        .set(GameState::GameOver);
+    app.update();
+    app.world.send_event(AppExit);
+    app.update();
+    assert!(!file.0.exists());
+    assert!(file.load(app.world.resource::<Level>()).is_none());
+}
+
+#[test]
+fn saves_of_other_levels_are_ignored() {
+    let mut app = game_in_progress();
+    let snapshot = capture(&mut app);
+    let level = app.world.resource::<Level>().clone();
+    assert_eq!(snapshot.check(&level).ok(), Some(()));
+
+    let file =
+        SaveFile(std::env::temp_dir().join(format!("combobox-{}.level.ron", std::process::id())));
+    snapshot.save(&file.0).unwrap();
+    let edited = Level {
+        width: level.width + 10.0,
+        ..level.clone()
+    };
+    let loaded = (file.load(&level), file.load(&edited));
+    file.remove().unwrap();
+    assert_eq!(loaded, (Some(snapshot), None));
+}